@@ -23,7 +23,7 @@ void bwrite(struct buf *);
 void bpin(struct buf *);
 void bunpin(struct buf *);
 
-// exec.c
+// exec.rs
 int exec(char *, char **);
 
 // file.c
@@ -51,6 +51,7 @@ int namecmp(const char *, const char *);
 struct inode *namei(char *);
 struct inode *nameiparent(char *, char *);
 int readi(struct inode *, int, uint64, uint, uint);
+int ipermission(struct inode *, int);
 void stati(struct inode *, struct stat *);
 int writei(struct inode *, int, uint64, uint, uint);
 void itrunc(struct inode *);
@@ -84,6 +85,10 @@ pagetable_t proc_pagetable(struct proc *);
 void proc_freepagetable(pagetable_t, uint64);
 struct cpu *mycpu(void);
 struct proc *myproc();
+struct proc *mygroupleader();
+int proc_filelimit(struct proc *);
+int proc_uid(struct proc *);
+int proc_gid(struct proc *);
 void procinit(void);
 void sleep_lock(void *, struct spinlock *);
 void userinit(void);
@@ -119,6 +124,9 @@ void argaddr(int, uint64 *);
 int fetchstr(uint64, char *, int);
 int fetchaddr(uint64, uint64 *);
 
+// sysfile.c
+struct inode *create(char *, short, short, short);
+
 // trap.c
 void usertrapret(void);
 
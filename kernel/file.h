@@ -6,6 +6,7 @@ struct file {
   int ref; // reference count
   char readable;
   char writable;
+  char text;   // opened by exec, holding a textref on ip
   struct pipe *pipe; // FD_PIPE
   struct inode *ip;  // FD_INODE and FD_DEVICE
   uint off;          // FD_INODE
@@ -23,11 +24,15 @@ struct inode {
   int ref;            // Reference count
   struct sleeplock lock; // protects everything below here
   int valid;          // inode has been read from disk?
+  int textref;        // files running it as a program, so it can't be written
 
   short type;         // copy of disk inode
   short major;
   short minor;
   short nlink;
+  ushort uid;
+  ushort gid;
+  ushort mode;
   uint size;
   uint addrs[NDIRECT+1];
 };
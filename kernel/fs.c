@@ -234,6 +234,9 @@ iupdate(struct inode *ip)
   dip->major = ip->major;
   dip->minor = ip->minor;
   dip->nlink = ip->nlink;
+  dip->uid = ip->uid;
+  dip->gid = ip->gid;
+  dip->mode = ip->mode;
   dip->size = ip->size;
   memmove(dip->addrs, ip->addrs, sizeof(ip->addrs));
   log_write(bp);
@@ -307,6 +310,9 @@ ilock(struct inode *ip)
     ip->major = dip->major;
     ip->minor = dip->minor;
     ip->nlink = dip->nlink;
+    ip->uid = dip->uid;
+    ip->gid = dip->gid;
+    ip->mode = dip->mode;
     ip->size = dip->size;
     memmove(ip->addrs, dip->addrs, sizeof(ip->addrs));
     brelse(bp);
@@ -462,7 +468,36 @@ stati(struct inode *ip, struct stat *st)
   st->ino = ip->inum;
   st->type = ip->type;
   st->nlink = ip->nlink;
+  st->mode = ip->mode;
   st->size = ip->size;
+  st->uid = ip->uid;
+  st->gid = ip->gid;
+}
+
+// Check that the current process may access ip in all the
+// ways in access, a combination of MAY_READ, MAY_WRITE and
+// MAY_EXEC. Root may do anything, except execute a file
+// that has no execute permission at all.
+// Returns 0 if it may, -1 if not.
+// Caller must hold ip->lock.
+int
+ipermission(struct inode *ip, int access)
+{
+  struct proc *p = myproc();
+  int uid = proc_uid(p);
+  int mode = ip->mode;
+
+  if(uid == 0){
+    if((access & MAY_EXEC) && ip->type == T_FILE && (mode & 0111) == 0)
+      return -1;
+    return 0;
+  }
+
+  if(uid == ip->uid)
+    mode >>= 6;
+  else if(proc_gid(p) == ip->gid)
+    mode >>= 3;
+  return (mode & access) == access ? 0 : -1;
 }
 
 // Read data from inode.
@@ -503,12 +538,15 @@ readi(struct inode *ip, int user_dst, uint64 dst, uint off, uint n)
 // Returns the number of bytes successfully written.
 // If the return value is less than the requested n,
 // there was an error of some kind.
+// A program that is running can't be written.
 int
 writei(struct inode *ip, int user_src, uint64 src, uint off, uint n)
 {
   uint tot, m;
   struct buf *bp;
 
+  if(ip->textref > 0)
+    return -1;
   if(off > ip->size || off + n < off)
     return -1;
   if(off + n > MAXFILE*BSIZE)
@@ -648,6 +686,7 @@ skipelem(char *path, char *name)
 // Look up and return the inode for a path name.
 // If parent != 0, return the inode for the parent and copy the final
 // path element into name, which must have room for DIRSIZ bytes.
+// Each directory searched must give the process execute permission.
 // Must be called inside a transaction since it calls iput().
 static struct inode*
 namex(char *path, int nameiparent, char *name)
@@ -657,7 +696,7 @@ namex(char *path, int nameiparent, char *name)
   if(*path == '/')
     ip = iget(ROOTDEV, ROOTINO);
   else
-    ip = idup(myproc()->cwd);
+    ip = idup(mygroupleader()->cwd);
 
   while((path = skipelem(path, name)) != 0){
     ilock(ip);
@@ -670,7 +709,7 @@ namex(char *path, int nameiparent, char *name)
       iunlock(ip);
       return ip;
     }
-    if((next = dirlookup(ip, name, 0)) == 0){
+    if(ipermission(ip, MAY_EXEC) < 0 || (next = dirlookup(ip, name, 0)) == 0){
       iunlockput(ip);
       return 0;
     }
@@ -25,7 +25,7 @@ struct superblock {
 
 #define FSMAGIC 0x10203040
 
-#define NDIRECT 12
+#define NDIRECT 10
 #define NINDIRECT (BSIZE / sizeof(uint))
 #define MAXFILE (NDIRECT + NINDIRECT)
 
@@ -35,10 +35,19 @@ struct dinode {
   short major;          // Major device number (T_DEVICE only)
   short minor;          // Minor device number (T_DEVICE only)
   short nlink;          // Number of links to inode in file system
+  ushort uid;           // Owner's user ID
+  ushort gid;           // Owner's group ID
+  ushort mode;          // Permission bits, as for chmod()
+  ushort pad;
   uint size;            // Size of file (bytes)
   uint addrs[NDIRECT+1];   // Data block addresses
 };
 
+// Kinds of access for ipermission(), as in each group of mode bits.
+#define MAY_READ  4
+#define MAY_WRITE 2
+#define MAY_EXEC  1
+
 // Inodes per block.
 #define IPB           (BSIZE / sizeof(struct dinode))
 
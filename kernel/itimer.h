@@ -0,0 +1,7 @@
+// Interval timers, as set by setitimer(). Times are in timer ticks.
+#define ITIMER_REAL 0 // Counts real time and sends SIGALRM
+
+struct itimerval {
+  uint64 it_interval; // Ticks between expirations, or 0 to expire once
+  uint64 it_value;    // Ticks until the next expiration, or 0 to disarm
+};
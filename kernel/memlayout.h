@@ -65,6 +65,7 @@
 //   fixed-size stack
 //   expandable heap
 //   ...
+//   thread trapframes (one per thread sharing the page table)
 //   TRAPFRAME (p->trapframe, used by the trampoline)
 //   TRAMPOLINE (the same page as in the kernel)
 #define TRAPFRAME (TRAMPOLINE - PGSIZE)
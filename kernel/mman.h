@@ -0,0 +1,15 @@
+// mmap() protection.
+#define PROT_READ  0x1
+#define PROT_WRITE 0x2
+#define PROT_EXEC  0x4
+
+// mmap() flags.
+#define MAP_SHARED    0x01 // Share changes with other mappings
+#define MAP_PRIVATE   0x02 // Changes are private to the process
+#define MAP_ANONYMOUS 0x20 // Zeroed memory rather than a file
+
+#define MAP_FAILED ((void*)-1)
+
+// msync() flags. Writes are always synchronous.
+#define MS_ASYNC 1
+#define MS_SYNC  4
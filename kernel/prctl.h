@@ -0,0 +1,3 @@
+// prctl() options.
+#define PR_GET_DUMPABLE 3 // Whether a fault writes a core file
+#define PR_SET_DUMPABLE 4 // Turn core files on (1) or off (0)
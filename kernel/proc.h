@@ -29,6 +29,10 @@ struct cpu {
   struct context context;     // swtch() here to enter scheduler().
   int interrupt_disable_layers;    // Depth of push_off() nesting.
   int previous_interrupts_enabled; // Were interrupts enabled before push_off()?
+  uint64 kernel_stacks_seen;       // Process table entries with kernel stacks mapped on this hart
+  uint64 idle_ticks;               // Timer ticks spent with nothing to run
+  uint64 timer_interrupts;         // Timer interrupts taken
+  uint64 tick_start;               // mtime when the first uncharged tick started
 };
 
 // per-process data for the trap handling code in trampoline.S.
@@ -82,7 +86,7 @@ struct trapframe {
   /* 280 */ uint64 t6;
 };
 
-enum procstate { UNUSED, USED, SLEEPING, RUNNABLE, RUNNING, ZOMBIE };
+enum procstate { UNUSED, USED, SLEEPING, RUNNABLE, RUNNING, ZOMBIE, STOPPED };
 
 // Per-process state
 struct proc {
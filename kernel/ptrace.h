@@ -0,0 +1,22 @@
+// ptrace() requests.
+#define PTRACE_TRACEME    0  // Be traced by the parent
+#define PTRACE_PEEKDATA   2  // Read the word at addr into *data
+#define PTRACE_POKEDATA   5  // Write the word data to addr
+#define PTRACE_CONT       7  // Continue, taking signal data if not 0
+#define PTRACE_KILL       8  // Kill the tracee
+#define PTRACE_SINGLESTEP 9  // Continue for one instruction
+#define PTRACE_GETREGS    12 // Copy the registers to *data
+#define PTRACE_SETREGS    13 // Set the registers from *data
+#define PTRACE_ATTACH     16 // Start tracing a child, stopping it
+#define PTRACE_DETACH     17 // Stop tracing, continuing as for PTRACE_CONT
+
+// Registers, as saved in the tracee's trapframe.
+// The kernel_* fields can't be changed.
+struct regs {
+  uint64 kernel_satp;
+  uint64 kernel_sp;
+  uint64 kernel_trap;
+  uint64 epc;
+  uint64 kernel_hartid;
+  uint64 x[31]; // x1 (ra) through x31 (t6)
+};
@@ -0,0 +1,13 @@
+// Resource limits, for getrlimit() and setrlimit().
+#define RLIMIT_CPU    0 // Timer ticks of CPU time
+#define RLIMIT_AS     1 // Bytes of user memory
+#define RLIMIT_NOFILE 2 // One more than the highest file descriptor
+#define RLIMIT_NPROC  3 // Children at once
+#define RLIMIT_CORE   4 // Bytes of core file, 0 for none
+
+#define RLIM_INFINITY 0xffffffffffffffffULL // No limit
+
+struct rlimit {
+  uint64 rlim_cur; // Soft limit, which is enforced
+  uint64 rlim_max; // Hard limit, the ceiling for the soft limit
+};
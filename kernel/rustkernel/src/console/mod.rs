@@ -7,6 +7,8 @@
 // - ctrl-u: kill line
 // - ctrl-d: end of file
 // - ctrl-p: print process list
+// - ctrl-c: interrupt the foreground process group
+// - ctrl-z: stop the foreground process group
 
 pub mod printf;
 
@@ -17,6 +19,7 @@ use crate::{
     proc::{
         process::{procdump, Process},
         scheduler::wakeup,
+        signal::{SIGINT, SIGTSTP},
     },
     sync::mutex::Mutex,
 };
@@ -32,6 +35,8 @@ pub struct Console {
     pub read_index: usize,
     pub write_index: usize,
     pub edit_index: usize,
+    /// Process group that ctrl-c and ctrl-z are sent to, or 0 for none.
+    pub foreground_group: i32,
 }
 impl Console {
     pub fn read_byte(&self) -> &u8 {
@@ -58,8 +63,18 @@ pub static cons: Mutex<Console> = Mutex::new(Console {
     read_index: 0,
     write_index: 0,
     edit_index: 0,
+    foreground_group: 0,
 });
 
+/// Set the process group that ctrl-c and ctrl-z are sent to.
+pub fn set_foreground_group(pgid: i32) {
+    cons.lock_spinning().foreground_group = pgid;
+}
+
+pub fn foreground_group() -> i32 {
+    cons.lock_spinning().foreground_group
+}
+
 /// ctrl-x
 const fn ctrl_x(x: u8) -> u8 {
     x - b'@'
@@ -117,7 +132,7 @@ pub fn consoleread(user_dst: i32, mut dst: u64, mut n: i32) -> i32 {
             // Wait until interrupt handler has put
             // some input into cons.buffer.
             while console.read_index == console.write_index {
-                if Process::current().unwrap().is_killed() {
+                if Process::current().unwrap().is_interrupted() {
                     // cons.lock.unlock();
                     return -1;
                 }
@@ -180,6 +195,22 @@ pub fn consoleintr(mut c: u8) {
     if c == ctrl_x(b'P') {
         // Print process list.
         unsafe { procdump() };
+    } else if c == ctrl_x(b'C') || c == ctrl_x(b'Z') {
+        // Discard the line being typed, and signal the foreground process group.
+        let echo = if c == ctrl_x(b'C') { b"^C\n" } else { b"^Z\n" };
+        for &b in echo {
+            consputc(b);
+        }
+        console.edit_index = console.write_index;
+
+        let pgid = console.foreground_group;
+        drop(console);
+
+        if pgid > 0 {
+            let signal = if c == ctrl_x(b'C') { SIGINT } else { SIGTSTP };
+            // Signal the group directly, since kill(-1) means every process.
+            let _ = unsafe { Process::kill_group(signal, |p| p.pgid == pgid) };
+        }
     } else if c == ctrl_x(b'U') {
         // Kill line.
         while console.edit_index != console.write_index
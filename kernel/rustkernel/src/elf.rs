@@ -0,0 +1,54 @@
+//! Format of an ELF file, as in elf.h.
+
+/// "\x7FELF" in little endian.
+pub const ELF_MAGIC: u32 = 0x464C457F;
+
+/// ElfHeader kind of a core dump.
+pub const ELF_CORE: u16 = 4;
+/// ElfHeader machine for RISC-V.
+pub const ELF_MACHINE_RISCV: u16 = 243;
+
+/// Values for ProgramHeader kind.
+pub const ELF_PROG_LOAD: u32 = 1;
+pub const ELF_PROG_NOTE: u32 = 4;
+
+/// Flag bits for ProgramHeader flags.
+pub const ELF_PROG_FLAG_EXEC: u32 = 1;
+pub const ELF_PROG_FLAG_WRITE: u32 = 2;
+pub const ELF_PROG_FLAG_READ: u32 = 4;
+
+/// File header.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct ElfHeader {
+    /// Must equal ELF_MAGIC.
+    pub magic: u32,
+    pub elf: [u8; 12],
+    pub kind: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// Program section header.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub off: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
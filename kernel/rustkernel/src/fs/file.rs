@@ -28,6 +28,8 @@ pub struct File {
     pub references: i32,
     pub readable: u8,
     pub writable: u8,
+    /// Opened by exec(), holding a text reference on the inode
+    pub text: u8,
     /// FileType::Pipe
     pub pipe: *mut Pipe,
     /// FileType::Inode and FileType::Device
@@ -45,6 +47,7 @@ impl File {
             references: 0,
             readable: 0,
             writable: 0,
+            text: 0,
             pipe: null_mut(),
             ip: null_mut(),
             off: 0,
@@ -82,6 +85,7 @@ pub unsafe extern "C" fn filealloc() -> *mut File {
     for file in files.as_mut() {
         if file.references == 0 {
             file.references = 1;
+            file.text = 0;
             return addr_of_mut!(*file);
         }
     }
@@ -125,6 +129,10 @@ pub unsafe extern "C" fn fileclose(file: *mut File) {
             FileType::Pipe => (*f.pipe).close(f.writable as i32),
             FileType::Inode | FileType::Device => {
                 let _operation = log::LogOperation::new();
+                if f.text != 0 {
+                    let _guard = InodeLockGuard::new(&mut *f.ip);
+                    (*f.ip).text_references -= 1;
+                }
                 iput(f.ip);
             }
             FileType::None => {}
@@ -15,9 +15,15 @@ extern "C" {
     pub fn readi(ip: *mut Inode, user_dst: i32, dst: u64, off: u32, n: u32) -> i32;
     pub fn writei(ip: *mut Inode, user_src: i32, src: u64, off: u32, n: u32) -> i32;
     pub fn namei(path: *mut u8) -> *mut Inode;
+    pub fn ipermission(ip: *mut Inode, access: i32) -> i32;
     // pub fn namecmp()
 }
 
+/// Kinds of access for ipermission(), as in each group of mode bits.
+pub const MAY_READ: i32 = 4;
+pub const MAY_WRITE: i32 = 2;
+pub const MAY_EXEC: i32 = 1;
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct Inode {
@@ -31,12 +37,17 @@ pub struct Inode {
     pub lock: Sleeplock,
     /// Inode has been read from disk?
     pub valid: i32,
+    /// Files running it as a program, so it can't be written.
+    pub text_references: i32,
 
     // Copy of DiskInode
     pub kind: i16,
     pub major: i16,
     pub minor: i16,
     pub num_links: i16,
+    pub uid: u16,
+    pub gid: u16,
+    pub mode: u16,
     pub size: u32,
     pub addresses: [u32; crate::fs::NDIRECT + 1],
 }
@@ -37,7 +37,7 @@ pub struct Superblock {
 }
 
 pub const FSMAGIC: u32 = 0x10203040;
-pub const NDIRECT: usize = 12;
+pub const NDIRECT: usize = 10;
 pub const NINDIRECT: usize = BSIZE as usize / core::mem::size_of::<u32>();
 pub const MAXFILE: usize = NDIRECT + NINDIRECT;
 
@@ -52,6 +52,13 @@ pub struct DiskInode {
     pub minor: i16,
     /// Number of links to inode in file system.
     pub nlink: i16,
+    /// Owner's user ID.
+    pub uid: u16,
+    /// Owner's group ID.
+    pub gid: u16,
+    /// Permission bits, as for chmod().
+    pub mode: u16,
+    pub pad: u16,
     /// Size of file (bytes).
     pub size: u32,
     /// Data block addresses.
@@ -13,6 +13,12 @@ pub struct Stat {
     pub kind: i16,
     /// Number of links to file.
     pub num_links: i16,
+    /// Permission bits.
+    pub mode: u16,
     /// Size of file in bytes.
     pub size: u64,
+    /// Owner's user ID.
+    pub uid: u32,
+    /// Owner's group ID.
+    pub gid: u32,
 }
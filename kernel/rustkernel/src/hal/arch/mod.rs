@@ -13,7 +13,7 @@ pub mod interrupt {
     pub use crate::hal::{
         arch::riscv::asm::{
             intr_get as interrupts_enabled, intr_off as disable_interrupts,
-            intr_on as enable_interrupts,
+            intr_on as enable_interrupts, wfi as wait_for_interrupt,
         },
         hardware::riscv::plic::{
             plic_claim as handle_interrupt, plic_complete as complete_interrupt, plicinit as init,
@@ -27,7 +27,8 @@ pub mod mem {
     pub use super::riscv::{
         asm::sfence_vma as flush_cached_pages,
         mem::{
-            kstack, Pagetable, PAGE_SIZE, PHYSICAL_END, PTE_R, PTE_W, PTE_X, TRAMPOLINE, TRAPFRAME,
+            thread_trapframe, Pagetable, KERNEL_BASE, PAGE_SIZE, PHYSICAL_END, PTE_R, PTE_W, PTE_X,
+            TRAMPOLINE, TRAPFRAME,
         },
     };
 
@@ -43,13 +44,24 @@ pub mod mem {
 pub mod virtual_memory {
     #[cfg(target_arch = "riscv64")]
     pub use super::riscv::virtual_memory::{
-        copyin, copyinstr, copyout, either_copyin, either_copyout, kvminit as init,
-        kvminithart as inithart, mappages, uvmalloc, uvmcopy, uvmcreate, uvmdealloc, uvmfirst,
-        uvmfree, uvmunmap,
+        copyin, copyinstr, copyout, either_copyin, either_copyout, kstackalloc, kvminit as init,
+        kvminithart as inithart, mappages, uvmalloc, uvmclean, uvmclear, uvmcopy, uvmcreate,
+        uvmdealloc, uvmdirty, uvmfirst, uvmfree, uvminstall, uvmlazy, uvmunmap, walkaddr,
     };
 }
 
 pub mod clock {
+    #[cfg(all(target_arch = "riscv64", feature = "tickless"))]
+    pub use crate::hal::{
+        arch::riscv::trap::{charge_ticks_locked, program_timer},
+        hardware::riscv::clint::set_timer,
+    };
     #[cfg(target_arch = "riscv64")]
-    pub use super::riscv::trap::CLOCK_TICKS;
+    pub use crate::hal::{
+        arch::riscv::trap::{uptime, CLOCK_TICKS},
+        hardware::riscv::clint::{
+            boot_time, interrupt_hart, mtime as time, send_ipi, time_since_boot, TICK_INTERVAL,
+            TIMER_FREQUENCY,
+        },
+    };
 }
@@ -245,9 +245,21 @@ pub unsafe fn r_ra() -> u64 {
     x
 }
 
+// Stall the hart until an interrupt might need attention.
+#[inline(always)]
+pub unsafe fn wfi() {
+    asm!("wfi");
+}
+
 // Flush the Translation Look-aside Buffer (TLB).
 #[inline(always)]
 pub unsafe fn sfence_vma() {
     // The "zero, zero" means flush all TLB entries.
     asm!("sfence.vma zero, zero");
 }
+
+// Make instructions written as data visible to instruction fetch on this hart.
+#[inline(always)]
+pub unsafe fn fence_i() {
+    asm!("fence.i");
+}
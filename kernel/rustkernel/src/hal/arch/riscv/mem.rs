@@ -29,6 +29,11 @@ pub const PTE_W: i32 = 1 << 2;
 pub const PTE_X: i32 = 1 << 3;
 /// The PagetableEntry is user-accessible.
 pub const PTE_U: i32 = 1 << 4;
+/// The page has been written.
+pub const PTE_D: i32 = 1 << 7;
+/// The page is shared copy-on-write, and writable once copied.
+/// One of the bits the hardware leaves for software.
+pub const PTE_COW: i32 = 1 << 8;
 
 /// Page-based 39-bit virtual addressing.
 /// Details at section 5.4 of the RISC-V specification.
@@ -67,9 +72,15 @@ pub fn kstack(page: usize) -> usize {
 /// - fixed-size stack
 /// - expandable heap
 ///   ...
+/// - thread trapframes (one per thread sharing the page table)
 /// - TRAPFRAME (p->trapframe, used by the trampoline)
 /// - TRAMPOLINE (the same page as in the kernel)
 pub const TRAPFRAME: usize = TRAMPOLINE - PAGE_SIZE;
+/// Map the trapframes of threads beneath TRAPFRAME,
+/// one page for each process table slot.
+pub fn thread_trapframe(slot: usize) -> usize {
+    TRAPFRAME - (slot + 1) * PAGE_SIZE
+}
 
 // Convert a physical address to a PagetableEntry.
 pub fn pa2pte(pa: usize) -> usize {
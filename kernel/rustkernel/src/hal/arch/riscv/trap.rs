@@ -1,17 +1,33 @@
-use super::{asm, mem::make_satp, SSTATUS_SPIE, SSTATUS_SPP};
+use super::{
+    asm,
+    mem::make_satp,
+    virtual_memory::{enter_user_pagetable, leave_user_pagetable, uvmcowfault},
+    SSTATUS_SPIE, SSTATUS_SPP,
+};
+#[cfg(feature = "tickless")]
+use crate::{
+    hal::arch::clock::{set_timer, time, time_since_boot, TICK_INTERVAL},
+    time::{next_wakeup, take_wakeup, tick_time},
+};
 use crate::{
     hal::{
         arch::{
             interrupt,
             mem::{PAGE_SIZE, TRAMPOLINE},
         },
+        hardware::riscv::clint::take_timer_interrupt,
         platform::VIRTIO0_IRQ,
     },
     println,
     proc::{
+        alarm::check_alarms,
         cpu::Cpu,
-        process::{Process, ProcessState},
-        scheduler::{r#yield, wakeup},
+        mmap::{PROT_EXEC, PROT_READ, PROT_WRITE},
+        process::{Process, ProcessError, ProcessState},
+        scheduler::{
+            r#yield, update_load_averages, vruntime_per_tick, wakeup, LOAD_INTERVAL, RUN_QUEUES,
+        },
+        signal::{SIGBUS, SIGSEGV},
     },
     sync::mutex::Mutex,
     syscall::syscall,
@@ -37,12 +53,179 @@ pub unsafe fn trapinithart() {
     asm::w_stvec(kernelvec as usize as u64);
 }
 
-pub fn clockintr() {
+/// The number of ticks since boot.
+///
+/// With the tickless timer, ticks aren't counted by interrupts, so the
+/// count is brought up to date first. CLOCK_TICKS must not be held,
+/// and neither may any process lock.
+#[cfg(not(feature = "tickless"))]
+pub fn uptime() -> usize {
+    *CLOCK_TICKS.lock_spinning()
+}
+#[cfg(feature = "tickless")]
+pub fn uptime() -> usize {
     let mut ticks = CLOCK_TICKS.lock_spinning();
+    unsafe { advance_clock(&mut ticks) };
+    *ticks
+}
 
-    *ticks += 1;
-    unsafe {
+/// Catch the tick count up with mtime, then wake sleeping processes
+/// and check interval timers if hart 0 was asked to by now.
+#[cfg(feature = "tickless")]
+unsafe fn advance_clock(ticks: &mut usize) {
+    let now = (time_since_boot() / TICK_INTERVAL) as usize;
+    while *ticks < now {
+        *ticks += 1;
+        if *ticks % LOAD_INTERVAL == 0 {
+            update_load_averages();
+        }
+    }
+
+    if take_wakeup(time()) {
         wakeup(addr_of!(CLOCK_TICKS).cast_mut().cast());
+        check_alarms(*ticks);
+    }
+}
+
+/// Handle a timer interrupt on this hart.
+///
+/// Hart 0 keeps the global tick count and the load averages, and every
+/// hart charges the tick to the process it is running, or to idle time.
+/// With the tickless timer, whichever hart is interrupted catches the
+/// tick count up, and then sets its timer for its next deadline.
+pub fn clockintr() {
+    let cpu = Cpu::current();
+    cpu.timer_interrupts += 1;
+
+    #[cfg(not(feature = "tickless"))]
+    if Cpu::current_id() == 0 {
+        let mut ticks = CLOCK_TICKS.lock_spinning();
+
+        *ticks += 1;
+        unsafe {
+            wakeup(addr_of!(CLOCK_TICKS).cast_mut().cast());
+            if *ticks % LOAD_INTERVAL == 0 {
+                update_load_averages();
+            }
+            check_alarms(*ticks);
+        }
+    }
+    #[cfg(feature = "tickless")]
+    uptime();
+
+    // The interrupt came from user mode if the previous privilege was user.
+    charge_ticks(unsafe { asm::r_sstatus() } & SSTATUS_SPP == 0);
+    if let Some(p) = Process::current() {
+        unsafe { p.check_cpu_limit() };
+    }
+
+    #[cfg(feature = "tickless")]
+    unsafe {
+        program_timer()
+    };
+}
+
+/// Charge the ticks since the last charge to the process this hart is
+/// running, as user or system time, or else to idle time. Then check
+/// whether the process has used up its time slice or should be preempted.
+///
+/// Every timer interrupt is one tick, except with the tickless timer,
+/// where the ticks are counted from mtime. p.lock must not be held.
+pub fn charge_ticks(user: bool) {
+    let (elapsed, now) = ticks_to_charge();
+    match Process::current() {
+        Some(p) => unsafe {
+            p.lock.lock_unguarded();
+            charge_process(p, user, elapsed, now);
+            p.lock.unlock();
+        },
+        None => Cpu::current().idle_ticks += elapsed as u64,
+    }
+}
+/// charge_ticks() for the scheduler, which holds p.lock
+/// when the process it ran comes back to it.
+#[cfg(feature = "tickless")]
+pub fn charge_ticks_locked(user: bool) {
+    let (elapsed, now) = ticks_to_charge();
+    match Process::current() {
+        Some(p) => charge_process(p, user, elapsed, now),
+        None => Cpu::current().idle_ticks += elapsed as u64,
+    }
+}
+
+/// The ticks to charge since the last charge on this hart, and the
+/// tick count. This takes CLOCK_TICKS, so no process lock may be held.
+#[cfg(not(feature = "tickless"))]
+fn ticks_to_charge() -> (usize, usize) {
+    (1, *CLOCK_TICKS.lock_spinning())
+}
+/// The ticks to charge since the last charge on this hart, and the
+/// tick count, both from mtime, as the scheduler holds p.lock.
+#[cfg(feature = "tickless")]
+fn ticks_to_charge() -> (usize, usize) {
+    let cpu = Cpu::current();
+    let elapsed = (time() - cpu.tick_start) / TICK_INTERVAL;
+    cpu.tick_start += elapsed * TICK_INTERVAL;
+    (
+        elapsed as usize,
+        (time_since_boot() / TICK_INTERVAL) as usize,
+    )
+}
+
+/// Charge `elapsed` ticks to `p` at `now`. p.lock must be held.
+fn charge_process(p: &mut Process, user: bool, elapsed: usize, now: usize) {
+    if user {
+        p.user_ticks += elapsed as u64;
+    } else {
+        p.system_ticks += elapsed as u64;
+    }
+
+    if p.reservation.is_realtime() {
+        // Real-time processes run until their budget is used up.
+        p.reservation.budget = p.reservation.budget.saturating_sub(elapsed);
+        p.reservation.replenish(now);
+        p.time_slice = p.reservation.budget;
+    } else {
+        p.time_slice = p.time_slice.saturating_sub(elapsed);
+        p.vruntime += vruntime_per_tick(p.priority) * elapsed as u64;
+    }
+
+    // Preempt for any real-time process with an earlier deadline.
+    if let Some(deadline) = unsafe { RUN_QUEUES[Cpu::current_id()].next_deadline(now) } {
+        if !p.reservation.is_realtime() || deadline < p.reservation.absolute_deadline() {
+            p.time_slice = 0;
+        }
+    }
+}
+
+/// Set this hart's timer for the soonest of: the end of the running
+/// process's time slice, a real-time process on this hart getting its
+/// budget back, and, on hart 0, a deadline passed to wake_at().
+/// An idle hart with none of those gets no timer interrupts at all.
+#[cfg(feature = "tickless")]
+pub unsafe fn program_timer() {
+    let cpu_id = Cpu::current_id();
+    let cpu = Cpu::current();
+
+    loop {
+        let wakeup = if cpu_id == 0 { next_wakeup() } else { u64::MAX };
+        let mut deadline = wakeup;
+
+        if let Some(p) = Process::current() {
+            if p.time_slice > 0 {
+                deadline = deadline.min(cpu.tick_start + p.time_slice as u64 * TICK_INTERVAL);
+            }
+        }
+        if let Some(release) = RUN_QUEUES[cpu_id].next_release() {
+            deadline = deadline.min(tick_time(release));
+        }
+        set_timer(cpu_id, deadline);
+
+        // Another hart may have asked for an earlier wakeup
+        // after it was read, and been overwritten.
+        if cpu_id != 0 || next_wakeup() >= wakeup {
+            break;
+        }
     }
 }
 
@@ -83,17 +266,21 @@ pub unsafe fn devintr() -> i32 {
 
         1
     } else if scause == 0x8000000000000001 {
-        // Software interrupt from a machine-mode timer interrupt,
-        // forwarded by timervec in kernelvec.S.
-
-        if Cpu::current_id() == 0 {
-            clockintr();
-        }
+        // Software interrupt from a machine-mode timer interrupt or
+        // another hart's software interrupt, forwarded by timervec
+        // in kernelvec.S.
 
         // Acknowledge the software interrupt by
         // clearing the SSIP bit in sip.
         asm::w_sip(asm::r_sip() & !2);
 
+        // Another hart only needed this one to come into the
+        // kernel, as with flush_other_harts(), which it has.
+        if !take_timer_interrupt(Cpu::current_id()) {
+            return 1;
+        }
+        clockintr();
+
         2
     } else {
         0
@@ -105,6 +292,14 @@ pub unsafe fn devintr() -> i32 {
 pub unsafe extern "C" fn usertrapret() -> ! {
     let proc = Process::current().unwrap();
 
+    // Run the default action or set up the handler for any pending signals.
+    proc.deliver_signals();
+
+    // A tracer may have written breakpoints into the program.
+    if proc.traced {
+        asm::fence_i();
+    }
+
     // We're about to switch the destination of traps from
     // kerneltrap() to usertrap(), so turn off interrupts until
     // we're back in user space, where usertrap() is correct.
@@ -141,6 +336,7 @@ pub unsafe extern "C" fn usertrapret() -> ! {
 
     // Tell trampoline.S the user page table to switch to.
     let satp = make_satp(proc.pagetable);
+    enter_user_pagetable(proc.pagetable);
 
     // Jump to userret in trampoline.S at the top of memory, which
     // switches to the user page table, restores user registers,
@@ -149,8 +345,9 @@ pub unsafe extern "C" fn usertrapret() -> ! {
         TRAMPOLINE + (addr_of!(userret) as usize) - (addr_of!(trampoline) as usize);
     let trampoline_userret = trampoline_userret as *const ();
     // Rust's most dangerous function: core::mem::transmute
-    let trampoline_userret = core::mem::transmute::<*const (), fn(u64) -> !>(trampoline_userret);
-    trampoline_userret(satp)
+    let trampoline_userret =
+        core::mem::transmute::<*const (), fn(u64, u64) -> !>(trampoline_userret);
+    trampoline_userret(satp, proc.trapframe_va)
 }
 
 /// Interrupts and exceptions from kernel code go here via kernelvec,
@@ -176,11 +373,14 @@ pub unsafe extern "C" fn kerneltrap() {
             asm::r_stval()
         );
         panic!("kerneltrap");
-    } else if which_dev == 2
+    }
+
+    if which_dev == 2
         && Process::current().is_some()
         && Process::current().unwrap().state == ProcessState::Running
+        && Process::current().unwrap().time_slice == 0
     {
-        // Give up the CPU if this is a timer interrupt.
+        // Give up the CPU if the time slice is used up.
         r#yield();
     }
 
@@ -198,6 +398,7 @@ pub unsafe extern "C" fn usertrap() {
     if asm::r_sstatus() & SSTATUS_SPP != 0 {
         panic!("usertrap: not from user mode");
     }
+    leave_user_pagetable();
 
     // Send interrupts and exceptions to kerneltrap(),
     // since we're now in the kernel.
@@ -224,10 +425,27 @@ pub unsafe extern "C" fn usertrap() {
         interrupt::enable_interrupts();
 
         syscall();
+    } else if asm::r_scause() == 3 {
+        // Breakpoint
+        proc.breakpoint();
     }
 
+    // Store page fault on a copy-on-write page, or
+    // page fault on memory that is allocated on first use.
+    let fault = match asm::r_scause() {
+        12 => Some(proc.page_fault(asm::r_stval(), PROT_EXEC)),
+        13 => Some(proc.page_fault(asm::r_stval(), PROT_READ)),
+        15 if uvmcowfault(proc.pagetable, asm::r_stval() as usize) == 0 => Some(Ok(())),
+        15 => Some(proc.page_fault(asm::r_stval(), PROT_WRITE)),
+        _ => None,
+    };
+    let paged = matches!(fault, Some(Ok(())));
+
     let which_dev = devintr();
-    if asm::r_scause() != 8 && which_dev == 0 {
+    if let Some(Err(ProcessError::BusError)) = fault {
+        // The mapped file doesn't have the page any more.
+        proc.set_killed(SIGBUS);
+    } else if asm::r_scause() != 8 && asm::r_scause() != 3 && !paged && which_dev == 0 {
         println!(
             "usertrap(): unexpected scause {} {}\n\tsepc={} stval={}",
             asm::r_scause(),
@@ -235,15 +453,15 @@ pub unsafe extern "C" fn usertrap() {
             asm::r_sepc(),
             asm::r_stval()
         );
-        proc.set_killed(true);
+        proc.set_killed(SIGSEGV);
     }
 
     if proc.is_killed() {
         proc.exit(-1);
     }
 
-    // Give up the CPU if this is a timer interrupt.
-    if which_dev == 2 {
+    // Give up the CPU if the time slice is used up.
+    if which_dev == 2 && proc.time_slice == 0 {
         r#yield();
     }
 
@@ -6,19 +6,33 @@ use crate::{
                 asm,
                 mem::{
                     kstack, make_satp, pte2pa, Pagetable, PagetableEntry, KERNEL_BASE, PAGE_SIZE,
-                    PHYSICAL_END, PTE_R, PTE_U, PTE_V, PTE_W, PTE_X, TRAMPOLINE, VIRTUAL_MAX,
+                    PHYSICAL_END, PTE_COW, PTE_D, PTE_R, PTE_U, PTE_V, PTE_W, PTE_X, TRAMPOLINE,
+                    VIRTUAL_MAX,
                 },
             },
         },
-        hardware::riscv::plic::PLIC,
+        hardware::riscv::{
+            clint::{send_ipi, CLINT},
+            plic::PLIC,
+        },
     },
     mem::{
-        kalloc::{kalloc, kfree},
+        kalloc::{kalloc, kdup, kfree, kreferences},
         memmove, memset,
     },
-    proc::process::Process,
+    proc::{
+        cpu::Cpu,
+        mmap::{PROT_READ, PROT_WRITE},
+        process::Process,
+    },
+    sync::spinlock::Spinlock,
+    NCPU,
+};
+use core::{
+    hint::spin_loop,
+    ptr::{addr_of, addr_of_mut, null_mut},
+    sync::atomic::{fence, AtomicUsize, Ordering},
 };
-use core::ptr::{addr_of, addr_of_mut, null_mut};
 
 extern "C" {
     /// kernel.ld sets this to end of kernel code.
@@ -70,6 +84,9 @@ pub unsafe fn kvmmake() -> Pagetable {
     // PLIC
     kvmmap(pagetable, PLIC, PLIC, 0x400000, PTE_R | PTE_W);
 
+    // CLINT, for mtime and, with the tickless feature, mtimecmp.
+    kvmmap(pagetable, CLINT, CLINT, 0x10000, PTE_R | PTE_W);
+
     let etext_addr = addr_of!(etext) as usize;
 
     // Map kernel text executable and read-only.
@@ -100,22 +117,6 @@ pub unsafe fn kvmmake() -> Pagetable {
         PTE_R | PTE_X,
     );
 
-    // Allocate and map a kernel stack for each process.
-    for i in 0..crate::NPROC {
-        let page = kalloc();
-        if page.is_null() {
-            panic!("kalloc");
-        }
-        let virtual_addr = kstack(i);
-        kvmmap(
-            pagetable,
-            virtual_addr,
-            page as usize,
-            PAGE_SIZE,
-            PTE_R | PTE_W,
-        );
-    }
-
     pagetable
 }
 
@@ -203,6 +204,35 @@ pub unsafe extern "C" fn walkaddr(pagetable: Pagetable, virtual_addr: usize) ->
     pte2pa(*pte as usize) as u64
 }
 
+/// Allocate and map the kernel stack for the process
+/// table entry `index`, as processes are created.
+///
+/// Returns its virtual address, or 0 if out of memory.
+/// The caller must serialize changes to the kernel page table.
+pub unsafe fn kstackalloc(index: usize) -> usize {
+    let page = kalloc();
+    if page.is_null() {
+        return 0;
+    }
+
+    let virtual_addr = kstack(index);
+    if mappages(
+        KERNEL_PAGETABLE,
+        virtual_addr,
+        PAGE_SIZE,
+        page as usize,
+        PTE_R | PTE_W,
+    ) != 0
+    {
+        kfree(page);
+        return 0;
+    }
+
+    // Each hart flushes its TLB before first running on
+    // a new kernel stack, in scheduler().
+    virtual_addr
+}
+
 /// Add a mapping to the kernel page table.
 ///
 /// Only used when booting.
@@ -261,31 +291,120 @@ pub unsafe fn mappages(
     0
 }
 
+/// The user page table each hart is running in user space, or 0 while
+/// it is in the kernel, where its TLB holds no user mappings, since
+/// trampoline.S flushes it whenever it switches page tables.
+static USER_PAGETABLES: [AtomicUsize; NCPU] = [const { AtomicUsize::new(0) }; NCPU];
+/// How many times each hart has come into the kernel from user space.
+static USER_TRAPS: [AtomicUsize; NCPU] = [const { AtomicUsize::new(0) }; NCPU];
+
+/// Note that this hart is about to run `pagetable` in user space.
+/// Interrupts must be disabled.
+pub fn enter_user_pagetable(pagetable: Pagetable) {
+    USER_PAGETABLES[Cpu::current_id()].store(pagetable as usize, Ordering::SeqCst);
+}
+
+/// Note that this hart has come into the kernel from user space.
+/// Interrupts must be disabled.
+pub fn leave_user_pagetable() {
+    let cpu_id = Cpu::current_id();
+    USER_TRAPS[cpu_id].fetch_add(1, Ordering::SeqCst);
+    USER_PAGETABLES[cpu_id].store(0, Ordering::SeqCst);
+}
+
+/// Make sure no other hart still has `pagetable`'s old entries cached
+/// after some were changed, by interrupting the harts running it in user
+/// space and waiting until they have come into the kernel.
+///
+/// Must be called after taking away access to a page,
+/// and before anything that relied on that, like freeing it.
+pub unsafe fn flush_other_harts(pagetable: Pagetable) {
+    // Make the changed entries visible before looking for harts using them.
+    fence(Ordering::SeqCst);
+
+    // This hart is in the kernel, so it never waits for itself.
+    let mut traps = [None; NCPU];
+    for (hart, traps) in traps.iter_mut().enumerate() {
+        if USER_PAGETABLES[hart].load(Ordering::SeqCst) == pagetable as usize {
+            *traps = Some(USER_TRAPS[hart].load(Ordering::SeqCst));
+            send_ipi(hart);
+        }
+    }
+    for (hart, traps) in traps.iter().enumerate() {
+        let Some(traps) = *traps else {
+            continue;
+        };
+        while USER_PAGETABLES[hart].load(Ordering::SeqCst) == pagetable as usize
+            && USER_TRAPS[hart].load(Ordering::SeqCst) == traps
+        {
+            spin_loop();
+        }
+    }
+}
+
+/// Pages unmapped from a page table, freed together once
+/// no other hart can still be using them.
+struct UnmappedPages {
+    pagetable: Pagetable,
+    pages: [*mut u8; 32],
+    count: usize,
+}
+impl UnmappedPages {
+    fn new(pagetable: Pagetable) -> UnmappedPages {
+        UnmappedPages {
+            pagetable,
+            pages: [null_mut(); 32],
+            count: 0,
+        }
+    }
+    /// Add a page whose mapping has been removed.
+    unsafe fn push(&mut self, physical_addr: *mut u8) {
+        if self.count == self.pages.len() {
+            self.free();
+        }
+        self.pages[self.count] = physical_addr;
+        self.count += 1;
+    }
+    unsafe fn free(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        flush_other_harts(self.pagetable);
+        for &physical_addr in &self.pages[..self.count] {
+            kfree(physical_addr.cast());
+        }
+        self.count = 0;
+    }
+}
+
 /// Remove `npages` of mappings starting from `virtual_addr`.
 ///
 /// `virtual_addr` amust be page-aligned. The mappings must exist.
-/// Optionally free the physical memory.
+/// Optionally free the physical memory, once no other hart can use it.
 pub unsafe fn uvmunmap(pagetable: Pagetable, virtual_addr: usize, num_pages: usize, free: bool) {
     if virtual_addr % PAGE_SIZE != 0 {
         panic!("uvmunmap: not aligned");
     }
+    let mut unmapped = UnmappedPages::new(pagetable);
     let mut a = virtual_addr;
     while a < virtual_addr + num_pages * PAGE_SIZE {
         let pte = walk(pagetable, a, false);
-        if pte.is_null() {
-            panic!("uvmunmap: walk");
-        } else if (*pte) & PTE_V as u64 == 0 {
-            panic!("uvmunmap: not mapped");
+        if pte.is_null() || (*pte) & PTE_V as u64 == 0 {
+            // Heap pages are only mapped once they are used.
+            a += PAGE_SIZE;
+            continue;
         } else if ((*pte) & 0x3ffu64) == PTE_V as u64 {
             panic!("uvmunmap: not a leaf");
-        } else if free {
-            let physical_addr = (((*pte) >> 10) << 12) as usize as *mut u8;
-            kfree(physical_addr.cast());
         }
 
+        let physical_addr = (((*pte) >> 10) << 12) as usize as *mut u8;
         *pte = 0;
+        if free {
+            unmapped.push(physical_addr);
+        }
         a += PAGE_SIZE;
     }
+    unmapped.free();
 }
 
 /// Create an empty user pagetable.
@@ -403,50 +522,215 @@ pub unsafe fn uvmfree(pagetable: Pagetable, size: usize) {
     freewalk(pagetable);
 }
 
-/// Given a parent process's pagetable, copy
-/// its memory into a child's pagetable.
+/// Serializes page fault handling in user page tables,
+/// so that threads faulting on the same page handle it once.
+static FAULT_LOCK: Spinlock = Spinlock::new();
+
+/// Given a parent process's pagetable, copy its memory
+/// from `start` to `end` into a child's pagetable.
 ///
-/// Copies both the pagetable and the physical memory.
+/// Copies the pagetable, but shares the physical memory,
+/// making writable pages copy-on-write in both. Unless
+/// `copy_on_write` is set, the pages are shared for writing,
+/// and the child's are writable once written, as with uvmdirty().
 /// Returns 0 on success, -1 on failure.
 /// Frees any allocated pages on failure.
-pub unsafe fn uvmcopy(old: Pagetable, new: Pagetable, size: usize) -> i32 {
-    let mut i = 0;
+pub unsafe fn uvmcopy(
+    old: Pagetable,
+    new: Pagetable,
+    start: usize,
+    end: usize,
+    copy_on_write: bool,
+) -> i32 {
+    let mut i = start;
+    // Whether any of the parent's pages were made read-only.
+    let mut changed = false;
 
-    while i < size {
+    while i < end {
+        let _guard = FAULT_LOCK.lock();
         let pte = walk(old, i, false);
-        if pte.is_null() {
-            panic!("uvmcopy: PagetableEntry should exist");
-        } else if (*pte) & PTE_V as u64 == 0 {
-            panic!("uvmcopy: page not present");
+        if pte.is_null() || (*pte) & PTE_V as u64 == 0 {
+            // Not yet used, so the child can allocate its own.
+            i += PAGE_SIZE;
+            continue;
         }
 
+        if copy_on_write && (*pte) & PTE_W as u64 != 0 {
+            *pte = ((*pte) & !(PTE_W as u64)) | PTE_COW as u64;
+            changed = true;
+        }
         let pa = ((*pte) >> 10) << 12;
-        let flags = (*pte) & 0x3ffu64;
+        let mut flags = (*pte) & 0x3ffu64;
+        if !copy_on_write {
+            flags &= !((PTE_W | PTE_D) as u64);
+        }
 
-        let mem = kalloc();
-        if mem.is_null() {
-            uvmunmap(new, 0, i / PAGE_SIZE, true);
+        if mappages(new, i, PAGE_SIZE, pa as usize, flags as i32) != 0 {
+            uvmunmap(new, start, (i - start) / PAGE_SIZE, true);
+            if changed {
+                flush_other_harts(old);
+            }
             return -1;
         }
+        kdup(pa as *mut u8);
 
-        memmove(
-            mem.cast(),
-            (pa as usize as *mut u8).cast(),
-            PAGE_SIZE as u64 as u32,
-        );
+        i += PAGE_SIZE;
+    }
 
-        if mappages(new, i, PAGE_SIZE, mem as usize, flags as i32) != 0 {
-            kfree(mem.cast());
-            uvmunmap(new, 0, i / PAGE_SIZE, true);
+    // Threads of the parent on other harts could
+    // still write to the pages the child now shares.
+    if changed {
+        flush_other_harts(old);
+    }
+    0
+}
+
+/// Give `pagetable` its own copy of the page at `virtual_addr`
+/// if other page tables share it, so that it can be written.
+/// A copy-on-write page becomes writable.
+///
+/// Returns 0 on success, -1 if the page isn't
+/// mapped for user access or memory runs out.
+pub unsafe fn uvmunshare(pagetable: Pagetable, virtual_addr: usize) -> i32 {
+    if virtual_addr > VIRTUAL_MAX {
+        return -1;
+    }
+
+    let _guard = FAULT_LOCK.lock();
+    let pte = walk(pagetable, round_down_page(virtual_addr), false);
+    if pte.is_null() || *pte & PTE_V as u64 == 0 || *pte & PTE_U as u64 == 0 {
+        return -1;
+    }
+
+    let pa = pte2pa(*pte as usize) as *mut u8;
+    let mut flags = (*pte) & 0x3ffu64;
+    if flags & PTE_COW as u64 != 0 {
+        flags = (flags & !(PTE_COW as u64)) | PTE_W as u64;
+    }
+
+    if kreferences(pa) > 1 {
+        let mem = kalloc();
+        if mem.is_null() {
             return -1;
         }
+        memmove(mem.cast(), pa.cast(), PAGE_SIZE as u32);
+        *pte = (((mem as usize) >> 12) << 10) as PagetableEntry | flags;
+        flush_other_harts(pagetable);
+        kfree(pa);
+    } else {
+        *pte = ((*pte) & !0x3ffu64) | flags;
+    }
+    0
+}
 
-        i += PAGE_SIZE;
+/// Handle a store page fault at `virtual_addr` in user space.
+///
+/// Returns 0 if it was a copy-on-write page, which is now writable,
+/// or -1 if the process has no business writing there.
+pub unsafe fn uvmcowfault(pagetable: Pagetable, virtual_addr: usize) -> i32 {
+    if virtual_addr > VIRTUAL_MAX {
+        return -1;
     }
 
+    let pte = walk(pagetable, round_down_page(virtual_addr), false);
+    if pte.is_null() || *pte & PTE_COW as u64 == 0 {
+        // Another thread may have just copied the page.
+        return if !pte.is_null() && *pte & PTE_W as u64 != 0 && *pte & PTE_U as u64 != 0 {
+            0
+        } else {
+            -1
+        };
+    }
+
+    uvmunshare(pagetable, virtual_addr)
+}
+
+/// Map a zeroed page at `virtual_addr` in user space with
+/// permissions `perm`, for memory that is allocated on first use.
+///
+/// Returns 0 if the page is now mapped with those permissions,
+/// or -1 if memory runs out or something else is mapped there.
+pub unsafe fn uvmlazy(pagetable: Pagetable, virtual_addr: usize, perm: i32) -> i32 {
+    let mem = kalloc();
+    if mem.is_null() {
+        return -1;
+    }
+    memset(mem, 0, PAGE_SIZE);
+    uvminstall(pagetable, virtual_addr, mem, perm)
+}
+
+/// Map the physical page `page` at `virtual_addr` in user space
+/// with permissions `perm`, unless a page is mapped there already,
+/// in which case `page` is freed.
+///
+/// Returns 0 if a page is now mapped with those permissions, or -1.
+pub unsafe fn uvminstall(
+    pagetable: Pagetable,
+    virtual_addr: usize,
+    page: *mut u8,
+    perm: i32,
+) -> i32 {
+    if virtual_addr > VIRTUAL_MAX {
+        kfree(page);
+        return -1;
+    }
+
+    let _guard = FAULT_LOCK.lock();
+    let va = round_down_page(virtual_addr);
+    let pte = walk(pagetable, va, false);
+    if !pte.is_null() && *pte & PTE_V as u64 != 0 {
+        // Another thread may have just mapped the page.
+        kfree(page);
+        let wanted = (PTE_U | perm) as u64;
+        return if *pte & wanted == wanted { 0 } else { -1 };
+    }
+
+    if mappages(pagetable, va, PAGE_SIZE, page as usize, PTE_U | perm) != 0 {
+        kfree(page);
+        return -1;
+    }
     0
 }
 
+/// Make the user page at `virtual_addr` writable, and mark it dirty.
+/// Pages of shared file mappings are mapped without write
+/// permission until they are written, to find the ones to write back.
+///
+/// Returns 0 on success, or -1 if no user page is mapped there.
+pub unsafe fn uvmdirty(pagetable: Pagetable, virtual_addr: usize) -> i32 {
+    if virtual_addr > VIRTUAL_MAX {
+        return -1;
+    }
+
+    let _guard = FAULT_LOCK.lock();
+    let pte = walk(pagetable, round_down_page(virtual_addr), false);
+    if pte.is_null() || *pte & PTE_V as u64 == 0 || *pte & PTE_U as u64 == 0 {
+        return -1;
+    }
+    *pte |= (PTE_W | PTE_D) as u64;
+    0
+}
+
+/// If the user page at `virtual_addr` is dirty, make it clean and
+/// read-only again, as it is about to be written back.
+///
+/// Returns the page's physical address if it was dirty, or 0.
+pub unsafe fn uvmclean(pagetable: Pagetable, virtual_addr: usize) -> u64 {
+    if virtual_addr > VIRTUAL_MAX {
+        return 0;
+    }
+
+    let _guard = FAULT_LOCK.lock();
+    let pte = walk(pagetable, round_down_page(virtual_addr), false);
+    if pte.is_null() || *pte & PTE_V as u64 == 0 || *pte & PTE_D as u64 == 0 {
+        return 0;
+    }
+    *pte &= !((PTE_W | PTE_D) as u64);
+    // Writes through other harts' cached entries wouldn't mark it dirty.
+    flush_other_harts(pagetable);
+    pte2pa(*pte as usize) as u64
+}
+
 /// Mark a PagetableEntry invalid for user access.
 ///
 /// Used by exec for the user stack guard page.
@@ -459,6 +743,26 @@ pub unsafe extern "C" fn uvmclear(pagetable: Pagetable, virtual_addr: usize) {
     *pte &= !(PTE_U as u64);
 }
 
+/// Look up the physical address of a user page for copying, allocating
+/// it first if it is unused memory of the current process that allows
+/// `access`, made of PROT_* bits. Returns 0 if the page can't be accessed.
+unsafe fn user_page(pagetable: Pagetable, virtual_addr: usize, access: i32) -> usize {
+    let pa = walkaddr(pagetable, virtual_addr) as usize;
+    if pa != 0 || !fault_in(pagetable, virtual_addr, access) {
+        return pa;
+    }
+    walkaddr(pagetable, virtual_addr) as usize
+}
+
+/// Handle a page fault at `virtual_addr` for the current process, if
+/// `pagetable` is its page table. Returns whether the fault was handled.
+unsafe fn fault_in(pagetable: Pagetable, virtual_addr: usize, access: i32) -> bool {
+    let Some(p) = Process::current() else {
+        return false;
+    };
+    p.pagetable == pagetable && p.page_fault(virtual_addr as u64, access).is_ok()
+}
+
 /// Copy from kernel to user.
 ///
 /// Copy `len` bytes from `src` to virtual address `dst_virtual_addr` in a given pagetable.
@@ -472,11 +776,23 @@ pub unsafe extern "C" fn copyout(
 ) -> i32 {
     while len > 0 {
         let va0 = round_down_page(dst_virtual_addr);
-        let pa0 = walkaddr(pagetable, va0) as usize;
+        let mut pa0 = user_page(pagetable, va0, PROT_WRITE);
         if pa0 == 0 {
             return -1;
         }
 
+        // Make the page writable: a clean page of a shared file mapping
+        // by marking it dirty, or a page shared with other page tables
+        // by copying it.
+        let pte = walk(pagetable, va0, false);
+        if *pte & PTE_W as u64 == 0 {
+            let dirtied = *pte & PTE_COW as u64 == 0 && fault_in(pagetable, va0, PROT_WRITE);
+            if !dirtied && uvmunshare(pagetable, va0) != 0 {
+                return -1;
+            }
+            pa0 = walkaddr(pagetable, va0) as usize;
+        }
+
         let mut n = PAGE_SIZE - (dst_virtual_addr - va0);
         if n > len {
             n = len;
@@ -507,7 +823,7 @@ pub unsafe extern "C" fn copyin(
 ) -> i32 {
     while len > 0 {
         let va0 = round_down_page(src_virtual_addr);
-        let pa0 = walkaddr(pagetable, va0) as usize;
+        let pa0 = user_page(pagetable, va0, PROT_READ);
         if pa0 == 0 {
             return -1;
         }
@@ -579,7 +895,7 @@ pub unsafe fn copyinstr(
 
     while !got_null && max > 0 {
         let va0 = round_down_page(src_virtual_addr);
-        let pa0 = walkaddr(pagetable, va0) as usize;
+        let pa0 = user_page(pagetable, va0, PROT_READ);
         if pa0 == 0 {
             return -1;
         }
@@ -1,47 +1,76 @@
 use crate::{
-    hal::arch::riscv::{asm, MIE_MTIE, MSTATUS_MIE},
+    hal::arch::riscv::{asm, MIE_MSIE, MIE_MTIE, MSTATUS_MIE},
     NCPU,
 };
-use core::ptr::addr_of;
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 // Core Local Interrupter (CLINT), which contains the timer.
 // I'm pretty sure the CLINT address is standardized to this location.
 pub const CLINT: usize = 0x2000000;
-const CLINT_MTIME: usize = CLINT + 0xbff8;
+pub const CLINT_MTIME: usize = CLINT + 0xbff8;
+
+/// Rate at which mtime counts, in Hz.
+pub const TIMER_FREQUENCY: u64 = 10_000_000;
+/// Cycles of mtime between timer interrupts, about 1/10th second in qemu.
+pub const TICK_INTERVAL: u64 = 1_000_000;
+
+/// Value of mtime when hart 0 started.
+static mut BOOT_TIME: u64 = 0;
 
 extern "C" {
     pub fn timervec();
 }
 
 #[no_mangle]
-pub static mut timer_scratch: [[u64; 5]; NCPU] = [[0u64; 5]; NCPU];
+pub static mut timer_scratch: [[u64; 7]; NCPU] = [[0u64; 7]; NCPU];
 
+fn clint_msip(hartid: usize) -> *mut u32 {
+    (CLINT + 4 * hartid) as *mut u32
+}
 fn clint_mtimecmp(hartid: usize) -> *mut u64 {
     (CLINT + 0x4000 + (8 * hartid)) as *mut u64
 }
 
-/// Arrange to receive timer interrupts.
+/// Arrange to receive timer interrupts, and software interrupts from
+/// other harts.
 ///
 /// They will arrive in machine mode at
 /// at timervec in kernelvec.S,
 /// which turns them into software interrupts for
 /// devintr() in trap.c.
+///
+/// With the tickless feature, only the first interrupt is
+/// scheduled here, and the kernel sets each one after that.
 pub unsafe fn timerinit() {
     // Each CPU has a separate source of timer interrupts.
     let id = asm::r_mhartid() as usize;
 
+    if id == 0 {
+        BOOT_TIME = mtime();
+    }
+
     // Ask the CLINT for a timer interrupt.
-    // cycles, about 1/10th second in qemu
-    let interval = 1_000_000u64;
-    *clint_mtimecmp(id) = *(CLINT_MTIME as *const u64) + interval;
+    let interval = TICK_INTERVAL;
+    *clint_mtimecmp(id) = mtime() + interval;
 
     // Prepare information in scratch[] for timervec.
     // scratch[0..=2]: Space for timervec to save registers.
     // scratch[3]: Address of CLINT MTIMECMP register.
-    // scratch[4]: Desired interval (in cycles) between timer interrupts.
-    let scratch: &mut [u64; 5] = &mut timer_scratch[id];
+    // scratch[4]: Desired interval (in cycles) between timer interrupts,
+    //             or 0 to leave the timer off until set_timer().
+    // scratch[5]: Address of CLINT MSIP register.
+    // scratch[6]: Set by timervec when the timer goes off.
+    let scratch: &mut [u64; 7] = &mut timer_scratch[id];
     scratch[3] = clint_mtimecmp(id) as usize as u64;
-    scratch[4] = interval;
+    scratch[4] = if cfg!(feature = "tickless") {
+        0
+    } else {
+        interval
+    };
+    scratch[5] = clint_msip(id) as usize as u64;
     asm::w_mscratch(addr_of!(scratch[0]) as usize as u64);
 
     // Set the machine-mode trap handler.
@@ -50,6 +79,47 @@ pub unsafe fn timerinit() {
     // Enable machine-mode interrupts.
     asm::w_mstatus(asm::r_mstatus() | MSTATUS_MIE);
 
-    // Enable machine-mode timer interrupts.
-    asm::w_mie(asm::r_mie() | MIE_MTIE);
+    // Enable machine-mode timer and software interrupts.
+    asm::w_mie(asm::r_mie() | MIE_MTIE | MIE_MSIE);
+}
+
+/// Read the CLINT's cycle counter, which runs at TIMER_FREQUENCY
+/// and is shared by all harts.
+pub fn mtime() -> u64 {
+    unsafe { (CLINT_MTIME as *const u64).read_volatile() }
+}
+
+/// Cycles of mtime since hart 0 started.
+pub fn time_since_boot() -> u64 {
+    mtime() - unsafe { BOOT_TIME }
+}
+
+/// mtime when hart 0 started.
+pub fn boot_time() -> u64 {
+    unsafe { BOOT_TIME }
+}
+
+/// Interrupt `hart` when mtime reaches `deadline`, instead of
+/// whenever it was going to be. u64::MAX turns the timer off.
+pub unsafe fn set_timer(hart: usize, deadline: u64) {
+    clint_mtimecmp(hart).write_volatile(deadline);
+}
+
+/// Interrupt `hart` right away, so that it notices
+/// whatever has changed and sets its timer again.
+pub unsafe fn interrupt_hart(hart: usize) {
+    set_timer(hart, 0);
+}
+
+/// Send `hart` a software interrupt, which timervec
+/// passes on to devintr() like a timer interrupt.
+pub unsafe fn send_ipi(hart: usize) {
+    clint_msip(hart).write_volatile(1);
+}
+
+/// Whether the timer has gone off on `hart` since this was last
+/// called, as opposed to it being sent a software interrupt.
+pub unsafe fn take_timer_interrupt(hart: usize) -> bool {
+    let fired = AtomicU64::from_ptr(addr_of_mut!(timer_scratch[hart][6]));
+    fired.swap(0, Ordering::SeqCst) != 0
 }
@@ -90,7 +90,7 @@ impl Pipe {
         let guard = self.lock.lock();
 
         while i < num_bytes {
-            if self.is_read_open == 0 || proc.is_killed() {
+            if self.is_read_open == 0 || proc.is_interrupted() {
                 return Err(PipeError::ProcessKilled);
             }
             if self.bytes_written == self.bytes_read + PIPESIZE as u32 {
@@ -119,7 +119,7 @@ impl Pipe {
 
         // DOC: pipe-empty
         while self.bytes_read == self.bytes_written && self.is_write_open > 0 {
-            if proc.is_killed() {
+            if proc.is_interrupted() {
                 return Err(PipeError::ProcessKilled);
             } else {
                 // DOC: piperead-sleep
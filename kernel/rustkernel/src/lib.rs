@@ -12,6 +12,7 @@ extern crate core;
 mod hal;
 
 mod console;
+mod elf;
 mod fs;
 mod io;
 mod mem;
@@ -20,6 +21,7 @@ mod queue;
 mod string;
 mod sync;
 mod syscall;
+mod time;
 
 use crate::{proc::cpu::Cpu, sync::mutex::Mutex};
 use core::{
@@ -32,8 +34,8 @@ pub(crate) use crate::console::printf::{print, println, uprint, uprintln};
 pub static mut STARTED: bool = false;
 pub static PANICKED: Mutex<bool> = Mutex::new(false);
 
-/// Maximum number of processes
-pub const NPROC: usize = 64;
+/// Default and highest maximum number of processes, see maxproc()
+pub const NPROC: usize = 1024;
 /// Maximum number of CPUs
 pub const NCPU: usize = 8;
 /// Maximum number of open files per process
@@ -66,7 +68,6 @@ pub unsafe fn main() -> ! {
         println!("\nxv6 kernel is booting");
         hal::arch::virtual_memory::init();
         hal::arch::virtual_memory::inithart();
-        proc::process::procinit();
         hal::arch::trap::inithart();
         hal::arch::interrupt::init();
         hal::arch::interrupt::inithart();
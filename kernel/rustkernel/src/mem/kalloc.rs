@@ -1,9 +1,13 @@
 //! Physical memory allocator, for user processes,
 //! kernel stacks, page-table pages,
 //! and pipe buffers. Allocates whole 4096-byte pages.
+//!
+//! Each page has a reference count, so that copy-on-write fork()
+//! can share user pages between page tables. A page goes back on
+//! the free list once kfree() has been called for every reference.
 
 use crate::{
-    hal::arch::mem::{round_up_page, PAGE_SIZE, PHYSICAL_END},
+    hal::arch::mem::{round_up_page, KERNEL_BASE, PAGE_SIZE, PHYSICAL_END},
     mem::memset,
     sync::spinlock::Spinlock,
 };
@@ -16,10 +20,14 @@ extern "C" {
     pub static mut end: [u8; 0];
 }
 
+/// Number of pages of physical memory.
+const NPAGES: usize = (PHYSICAL_END - KERNEL_BASE) / PAGE_SIZE;
+
 #[no_mangle]
 pub static mut kmem: KernelMemory = KernelMemory {
     lock: Spinlock::new(),
     freelist: null_mut(),
+    references: [0; NPAGES],
 };
 
 #[repr(C)]
@@ -30,6 +38,13 @@ pub struct Run {
 pub struct KernelMemory {
     pub lock: Spinlock,
     pub freelist: *mut Run,
+    /// References to each allocated page
+    pub references: [u16; NPAGES],
+}
+
+/// Index of the page at pa in the reference counts.
+fn page_index(pa: *mut u8) -> usize {
+    (pa as usize - KERNEL_BASE) / PAGE_SIZE
 }
 
 pub unsafe fn kinit() {
@@ -41,15 +56,17 @@ unsafe fn freerange(pa_start: *mut u8, pa_end: *mut u8) {
     let mut p = round_up_page(pa_start as usize) as *mut u8;
 
     while p.add(PAGE_SIZE) <= pa_end {
+        kmem.references[page_index(p)] = 1;
         kfree(p.cast());
         p = p.add(PAGE_SIZE);
     }
 }
 
-/// Free the page of physical memory pointed at by pa,
-/// which normally should have been returned by a call
-/// to kalloc(). The exception is when initializing the
-/// allocator - see kinit above.
+/// Drop a reference to the page of physical memory pointed
+/// at by pa, freeing it if it was the last one. The page
+/// normally should have been returned by a call to kalloc().
+/// The exception is when initializing the allocator - see
+/// kinit above.
 #[no_mangle]
 pub unsafe extern "C" fn kfree(pa: *mut u8) {
     if (pa as usize % PAGE_SIZE) != 0
@@ -59,6 +76,18 @@ pub unsafe extern "C" fn kfree(pa: *mut u8) {
         panic!("kfree");
     }
 
+    {
+        let _guard = kmem.lock.lock();
+        let references = &mut kmem.references[page_index(pa)];
+        if *references == 0 {
+            panic!("kfree: page not allocated");
+        }
+        *references -= 1;
+        if *references > 0 {
+            return;
+        }
+    }
+
     memset(pa, 0, PAGE_SIZE);
 
     let run: *mut Run = pa.cast();
@@ -68,6 +97,19 @@ pub unsafe extern "C" fn kfree(pa: *mut u8) {
     kmem.freelist = run;
 }
 
+/// Add a reference to an allocated page, which
+/// then takes another kfree() to free.
+pub unsafe fn kdup(pa: *mut u8) {
+    let _guard = kmem.lock.lock();
+    kmem.references[page_index(pa)] += 1;
+}
+
+/// Number of references to an allocated page.
+pub unsafe fn kreferences(pa: *mut u8) -> usize {
+    let _guard = kmem.lock.lock();
+    kmem.references[page_index(pa)] as usize
+}
+
 /// Allocate one 4096-byte page of physical memory.
 ///
 /// Returns a pointer that the kernel can use.
@@ -79,6 +121,7 @@ pub unsafe extern "C" fn kalloc() -> *mut u8 {
     let run = kmem.freelist;
     if !run.is_null() {
         kmem.freelist = (*run).next;
+        kmem.references[page_index(run.cast())] = 1;
     }
 
     if !run.is_null() {
@@ -0,0 +1,116 @@
+//! Interval timers, which send SIGALRM when they expire.
+//!
+//! Each process has one timer, counting ticks of `CLOCK_TICKS`. It can
+//! fire once, as set by alarm(), or periodically, as set by setitimer().
+//! Timers are checked by clockintr(), aren't inherited by fork(), and
+//! are disarmed by exec().
+
+use super::{
+    process::{Process, ProcessError},
+    signal::SIGALRM,
+    table::PROCESSES,
+};
+use crate::time::{tick_time, wake_at};
+
+/// The setitimer() timer that counts real time, and the only one there is.
+pub const ITIMER_REAL: i32 = 0;
+/// Longest timer value or interval, in ticks: over thirteen years,
+/// and short enough that deadlines can't overflow.
+const MAX_TIMER_TICKS: u64 = u32::MAX as u64;
+
+/// A timer setting, as used by setitimer().
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct IntervalTimer {
+    /// Ticks between expirations after the first, or 0 to only expire once
+    pub interval: u64,
+    /// Ticks until the next expiration, or 0 if the timer is disarmed
+    pub value: u64,
+}
+
+impl Process {
+    /// Arm the timer to expire `timer.value` ticks after `now`,
+    /// or disarm it if that's 0. Returns the previous setting.
+    pub fn set_interval_timer(
+        &mut self,
+        timer: IntervalTimer,
+        now: usize,
+    ) -> Result<IntervalTimer, ProcessError> {
+        if timer.value > MAX_TIMER_TICKS || timer.interval > MAX_TIMER_TICKS {
+            return Err(ProcessError::InvalidArgument);
+        }
+        let deadline = now
+            .checked_add(timer.value as usize)
+            .ok_or(ProcessError::InvalidArgument)?;
+
+        let _guard = self.lock.lock();
+        let old_timer = IntervalTimer {
+            interval: self.alarm_interval as u64,
+            value: if self.alarm_deadline == 0 {
+                0
+            } else {
+                self.alarm_deadline.saturating_sub(now).max(1) as u64
+            },
+        };
+
+        if timer.value == 0 {
+            self.alarm_deadline = 0;
+            self.alarm_interval = 0;
+        } else {
+            self.alarm_deadline = deadline;
+            self.alarm_interval = timer.interval as usize;
+            wake_at(tick_time(self.alarm_deadline));
+        }
+        Ok(old_timer)
+    }
+    /// Disarm the timer, since exec() replaces the program that set it.
+    pub fn disarm_alarm(&mut self) {
+        let _guard = self.lock.lock();
+        self.alarm_deadline = 0;
+        self.alarm_interval = 0;
+    }
+
+    /// Send SIGALRM if the timer has expired by `now`,
+    /// and rearm it if it is periodic. p.lock must be held.
+    unsafe fn check_alarm(&mut self, now: usize) {
+        if self.alarm_deadline == 0 || self.alarm_deadline > now {
+            return;
+        }
+
+        self.send_signal(SIGALRM);
+
+        if self.alarm_interval == 0 {
+            self.alarm_deadline = 0;
+        } else {
+            // Skip any expirations that were missed.
+            while self.alarm_deadline <= now {
+                let Some(deadline) = self.alarm_deadline.checked_add(self.alarm_interval) else {
+                    self.alarm_deadline = 0;
+                    self.alarm_interval = 0;
+                    return;
+                };
+                self.alarm_deadline = deadline;
+            }
+        }
+    }
+}
+
+/// Check the timer of every process.
+///
+/// Called by clockintr() on hart 0 at every tick, or with the
+/// tickless timer, whenever hart 0 is woken by wake_at().
+pub unsafe fn check_alarms(now: usize) {
+    for p in PROCESSES.iter_mut() {
+        // Most processes have no timer, so don't bother locking them.
+        if p.alarm_deadline == 0 {
+            continue;
+        }
+
+        p.lock.lock_unguarded();
+        p.check_alarm(now);
+        if p.alarm_deadline != 0 {
+            wake_at(tick_time(p.alarm_deadline));
+        }
+        p.lock.unlock();
+    }
+}
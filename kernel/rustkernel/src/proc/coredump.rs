@@ -0,0 +1,346 @@
+//! Core files, for debugging a process after a fault kills it.
+//!
+//! A core file is an ELF file with a PT_LOAD segment for each run of
+//! mapped pages below `memory_allocated`, and a PT_NOTE segment with the
+//! registers from the Trapframe in an NT_PRSTATUS note, laid out as on
+//! Linux so that gdb can read it. It is written to core.<pid> in the
+//! current directory, unless the process has turned core dumps off with
+//! prctl() or the file would be bigger than its RLIMIT_CORE soft limit.
+
+use super::{
+    limit::RLIMIT_CORE,
+    process::{Process, ProcessError},
+    signal::{
+        signal_bit, NSIG, SIGABRT, SIGBUS, SIGFPE, SIGILL, SIGQUIT, SIGSEGV, SIGTRAP, SIGXCPU,
+    },
+};
+use crate::{
+    elf::{
+        ElfHeader, ProgramHeader, ELF_CORE, ELF_MACHINE_RISCV, ELF_MAGIC, ELF_PROG_FLAG_EXEC,
+        ELF_PROG_FLAG_READ, ELF_PROG_FLAG_WRITE, ELF_PROG_LOAD, ELF_PROG_NOTE,
+    },
+    fs::{
+        inode::{
+            ipermission, iput, itrunc, iunlock, iupdate, writei, Inode, InodeLockGuard, MAY_WRITE,
+        },
+        log::LogOperation,
+        stat::KIND_FILE,
+    },
+    hal::arch::{clock::TICK_INTERVAL, mem::PAGE_SIZE, virtual_memory::walkaddr},
+    time::Timespec,
+};
+use core::{mem::size_of, ptr::addr_of};
+
+extern "C" {
+    fn create(path: *mut u8, kind: i16, major: i16, minor: i16) -> *mut Inode;
+}
+
+/// prctl() options.
+pub const PR_GET_DUMPABLE: i32 = 3;
+pub const PR_SET_DUMPABLE: i32 = 4;
+
+/// Signals that write a core file when they kill a process.
+const CORE_SIGNALS: u32 = signal_bit(SIGQUIT)
+    | signal_bit(SIGILL)
+    | signal_bit(SIGTRAP)
+    | signal_bit(SIGABRT)
+    | signal_bit(SIGBUS)
+    | signal_bit(SIGFPE)
+    | signal_bit(SIGSEGV)
+    | signal_bit(SIGXCPU);
+
+/// Note type holding a Prstatus.
+const NT_PRSTATUS: u32 = 1;
+
+pub fn dumps_core(signal: usize) -> bool {
+    signal < NSIG && CORE_SIGNALS & signal_bit(signal) != 0
+}
+
+/// Header of an ELF note, with its name.
+#[repr(C)]
+struct NoteHeader {
+    name_size: u32,
+    description_size: u32,
+    kind: u32,
+    /// "CORE", padded to a multiple of 4 bytes.
+    name: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Timeval {
+    seconds: u64,
+    microseconds: u64,
+}
+impl Timeval {
+    fn from_ticks(ticks: u64) -> Timeval {
+        let time = Timespec::from_cycles(ticks * TICK_INTERVAL);
+        Timeval {
+            seconds: time.seconds,
+            microseconds: time.nanoseconds / 1000,
+        }
+    }
+}
+
+/// The description of an NT_PRSTATUS note,
+/// as Linux's struct elf_prstatus on RISC-V.
+#[repr(C)]
+#[derive(Default)]
+struct Prstatus {
+    signal: i32,
+    code: i32,
+    errno: i32,
+    current_signal: i16,
+    pending_signals: u64,
+    held_signals: u64,
+    pid: i32,
+    ppid: i32,
+    pgid: i32,
+    sid: i32,
+    user_time: Timeval,
+    system_time: Timeval,
+    children_user_time: Timeval,
+    children_system_time: Timeval,
+    /// pc, then x1 through x31.
+    registers: [u64; 32],
+    fp_valid: i32,
+}
+
+/// A core file being written, one log transaction at a time.
+struct CoreFile {
+    inode: *mut Inode,
+    offset: u32,
+}
+impl CoreFile {
+    /// Append `len` bytes from `src`, an address in user memory
+    /// if `user` is set, or in the kernel otherwise.
+    unsafe fn write(&mut self, user: bool, src: u64, len: u64) -> Result<(), ProcessError> {
+        // Write a few blocks at a time, as filewrite() does.
+        let max = ((crate::MAXOPBLOCKS - 1 - 1 - 2) / 2) as u64 * crate::fs::BSIZE as u64;
+        let mut written = 0;
+
+        while written < len {
+            let n = (len - written).min(max) as u32;
+            let _operation = LogOperation::new();
+            let _guard = InodeLockGuard::new(&mut *self.inode);
+
+            if writei(self.inode, user as i32, src + written, self.offset, n) != n as i32 {
+                return Err(ProcessError::PageError);
+            }
+            self.offset += n;
+            written += n as u64;
+        }
+        Ok(())
+    }
+    unsafe fn write_struct<T>(&mut self, value: &T) -> Result<(), ProcessError> {
+        self.write(false, addr_of!(*value) as u64, size_of::<T>() as u64)
+    }
+}
+
+impl Process {
+    /// Write a core file for the process, killed by `signal`.
+    pub unsafe fn dump_core(&mut self, signal: usize) -> Result<(), ProcessError> {
+        let limit = self.soft_limit(RLIMIT_CORE);
+        let leader = self.leader();
+        if !leader.dumpable || limit == 0 {
+            return Err(ProcessError::PermissionDenied);
+        }
+        let memory_size = leader.memory_allocated;
+
+        // Work out how big the core file will be.
+        let mut segments = 0;
+        let mut segments_size = 0;
+        let mut start = 0;
+        while let Some((begin, end)) = self.next_mapped_run(start, memory_size) {
+            segments += 1;
+            segments_size += end - begin;
+            start = end;
+        }
+        let notes_offset =
+            (size_of::<ElfHeader>() + (segments + 1) * size_of::<ProgramHeader>()) as u64;
+        let notes_size = (size_of::<NoteHeader>() + size_of::<Prstatus>()) as u64;
+        if notes_offset + notes_size + segments_size > limit.min(u32::MAX as u64) {
+            return Err(ProcessError::Allocation);
+        }
+
+        let mut file = CoreFile {
+            inode: self.create_core_file()?,
+            offset: 0,
+        };
+        let result = self.write_core(&mut file, signal, segments, notes_offset, memory_size);
+
+        let _operation = LogOperation::new();
+        iput(file.inode);
+        result
+    }
+
+    /// Create core.<pid> in the current directory, or empty it if it
+    /// exists. Returns it unlocked.
+    unsafe fn create_core_file(&mut self) -> Result<*mut Inode, ProcessError> {
+        let mut path = *b"core.\0\0\0\0\0\0\0";
+        let mut digits = [0u8; 6];
+        let mut pid = self.pid as u32;
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (pid % 10) as u8;
+            pid /= 10;
+            len += 1;
+            if pid == 0 {
+                break;
+            }
+        }
+        for i in 0..len {
+            path[5 + i] = digits[len - 1 - i];
+        }
+
+        let _operation = LogOperation::new();
+        let inode = create(path.as_mut_ptr(), KIND_FILE, 0, 0);
+        if inode.is_null() {
+            return Err(ProcessError::PermissionDenied);
+        }
+        if (*inode).kind != KIND_FILE || ipermission(inode, MAY_WRITE) < 0 {
+            iunlock(inode);
+            iput(inode);
+            return Err(ProcessError::PermissionDenied);
+        }
+
+        itrunc(inode);
+        // Memory can hold secrets, so only the owner may read it.
+        if (*inode).uid as u32 == self.user_id() {
+            (*inode).mode = 0o600;
+            iupdate(inode);
+        }
+        iunlock(inode);
+        Ok(inode)
+    }
+
+    unsafe fn write_core(
+        &mut self,
+        file: &mut CoreFile,
+        signal: usize,
+        segments: usize,
+        notes_offset: u64,
+        memory_size: u64,
+    ) -> Result<(), ProcessError> {
+        // 64-bit, little-endian, ELF version 1.
+        let mut ident = [0u8; 12];
+        ident[..3].copy_from_slice(&[2, 1, 1]);
+        let header = ElfHeader {
+            magic: ELF_MAGIC,
+            elf: ident,
+            kind: ELF_CORE,
+            machine: ELF_MACHINE_RISCV,
+            version: 1,
+            phoff: size_of::<ElfHeader>() as u64,
+            ehsize: size_of::<ElfHeader>() as u16,
+            phentsize: size_of::<ProgramHeader>() as u16,
+            phnum: (segments + 1) as u16,
+            ..Default::default()
+        };
+        file.write_struct(&header)?;
+
+        let notes_size = (size_of::<NoteHeader>() + size_of::<Prstatus>()) as u64;
+        file.write_struct(&ProgramHeader {
+            kind: ELF_PROG_NOTE,
+            off: notes_offset,
+            filesz: notes_size,
+            ..Default::default()
+        })?;
+
+        let mut offset = notes_offset + notes_size;
+        let mut start = 0;
+        while let Some((begin, end)) = self.next_mapped_run(start, memory_size) {
+            file.write_struct(&ProgramHeader {
+                kind: ELF_PROG_LOAD,
+                flags: ELF_PROG_FLAG_READ | ELF_PROG_FLAG_WRITE | ELF_PROG_FLAG_EXEC,
+                off: offset,
+                vaddr: begin,
+                filesz: end - begin,
+                memsz: end - begin,
+                align: PAGE_SIZE as u64,
+                ..Default::default()
+            })?;
+            offset += end - begin;
+            start = end;
+        }
+
+        file.write_struct(&NoteHeader {
+            name_size: 5,
+            description_size: size_of::<Prstatus>() as u32,
+            kind: NT_PRSTATUS,
+            name: *b"CORE\0\0\0\0",
+        })?;
+        file.write_struct(&self.prstatus(signal))?;
+
+        let mut start = 0;
+        while let Some((begin, end)) = self.next_mapped_run(start, memory_size) {
+            file.write(true, begin, end - begin)?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// The first run of pages in [start, end) that user space can
+    /// access, skipping pages like the stack guard page.
+    unsafe fn next_mapped_run(&self, start: u64, end: u64) -> Option<(u64, u64)> {
+        let mapped = |va: u64| walkaddr(self.pagetable, va as usize) != 0;
+        let page = PAGE_SIZE as u64;
+
+        let mut begin = start;
+        while begin < end && !mapped(begin) {
+            begin += page;
+        }
+        if begin >= end {
+            return None;
+        }
+        let mut stop = begin;
+        while stop < end && mapped(stop) {
+            stop += page;
+        }
+        Some((begin, stop.min(end)))
+    }
+
+    unsafe fn prstatus(&mut self, signal: usize) -> Prstatus {
+        let trapframe = &*self.trapframe;
+        let mut registers = [0u64; 32];
+        registers[0] = trapframe.epc;
+        for (n, register) in registers.iter_mut().enumerate().skip(1) {
+            *register = trapframe.register(n);
+        }
+
+        let ppid = if self.parent.is_null() {
+            0
+        } else {
+            (*self.parent).pid
+        };
+        let _guard = self.lock.lock();
+        Prstatus {
+            signal: signal as i32,
+            current_signal: signal as i16,
+            pending_signals: self.pending_signals as u64,
+            held_signals: self.blocked_signals as u64,
+            pid: self.pid,
+            ppid,
+            pgid: self.pgid,
+            sid: self.sid,
+            user_time: Timeval::from_ticks(self.user_ticks),
+            system_time: Timeval::from_ticks(self.system_ticks),
+            children_user_time: Timeval::from_ticks(self.children_user_ticks),
+            children_system_time: Timeval::from_ticks(self.children_system_ticks),
+            registers,
+            ..Default::default()
+        }
+    }
+
+    /// Turn core dumps on or off for the process, as with prctl().
+    pub fn prctl(&mut self, option: i32, value: u64) -> Result<u64, ProcessError> {
+        match option {
+            PR_GET_DUMPABLE => Ok(self.leader().dumpable as u64),
+            PR_SET_DUMPABLE if value <= 1 => {
+                self.leader().dumpable = value == 1;
+                Ok(0)
+            }
+            _ => Err(ProcessError::InvalidArgument),
+        }
+    }
+}
@@ -14,6 +14,16 @@ pub struct Cpu {
     pub interrupt_disable_layers: i32,
     /// Were interrupts enabled before push_off()?
     pub previous_interrupts_enabled: i32,
+    /// Process table entries whose kernel stacks this hart has a
+    /// mapping for, being those with an index less than this.
+    pub kernel_stacks_seen: usize,
+    /// Timer ticks this hart has spent with nothing to run.
+    pub idle_ticks: u64,
+    /// Timer interrupts this hart has taken.
+    pub timer_interrupts: u64,
+    /// mtime at which the tick this hart hasn't charged yet started.
+    /// Only used by the tickless timer, which doesn't interrupt every tick.
+    pub tick_start: u64,
 }
 impl Cpu {
     pub const fn new() -> Cpu {
@@ -22,6 +32,10 @@ impl Cpu {
             context: Context::new(),
             interrupt_disable_layers: 0,
             previous_interrupts_enabled: 0,
+            kernel_stacks_seen: 0,
+            idle_ticks: 0,
+            timer_interrupts: 0,
+            tick_start: 0,
         }
     }
     /// Must be called with interrupts disabled
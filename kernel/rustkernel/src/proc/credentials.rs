@@ -0,0 +1,69 @@
+//! User and group IDs, as set by setuid() and setgid().
+//!
+//! IDs are kept by the thread group leader and copied by fork(). User 0
+//! is root, which passes every file permission check and may take on
+//! any IDs. Other users may only set the IDs they already have, and
+//! only signal or change the scheduling of their own processes.
+
+use super::process::{Process, ProcessError};
+
+/// The user ID that bypasses permission checks.
+pub const ROOT_UID: u32 = 0;
+
+impl Process {
+    pub fn user_id(&mut self) -> u32 {
+        self.leader().uid
+    }
+    pub fn group_id(&mut self) -> u32 {
+        self.leader().gid
+    }
+    /// Whether the process is running as root.
+    pub fn is_privileged(&mut self) -> bool {
+        self.user_id() == ROOT_UID
+    }
+    /// Whether a process running as `uid` may signal the
+    /// process or change how it is scheduled.
+    pub fn controllable_by(&mut self, uid: u32) -> bool {
+        uid == ROOT_UID || uid == self.user_id()
+    }
+
+    pub fn set_user_id(&mut self, uid: u32) -> Result<(), ProcessError> {
+        if uid > u16::MAX as u32 {
+            return Err(ProcessError::InvalidArgument);
+        } else if !self.is_privileged() && uid != self.user_id() {
+            return Err(ProcessError::PermissionDenied);
+        }
+        self.leader().uid = uid;
+        Ok(())
+    }
+    pub fn set_group_id(&mut self, gid: u32) -> Result<(), ProcessError> {
+        if gid > u16::MAX as u32 {
+            return Err(ProcessError::InvalidArgument);
+        } else if !self.is_privileged() && gid != self.group_id() {
+            return Err(ProcessError::PermissionDenied);
+        }
+        self.leader().gid = gid;
+        Ok(())
+    }
+}
+
+/// The user ID of a process, for the permission checks in fs.c.
+/// Kernel code running without a process acts as root.
+#[no_mangle]
+pub unsafe extern "C" fn proc_uid(p: *mut Process) -> i32 {
+    if p.is_null() {
+        ROOT_UID as i32
+    } else {
+        (*p).user_id() as i32
+    }
+}
+
+/// The group ID of a process, for the permission checks in fs.c.
+#[no_mangle]
+pub unsafe extern "C" fn proc_gid(p: *mut Process) -> i32 {
+    if p.is_null() {
+        0
+    } else {
+        (*p).group_id() as i32
+    }
+}
@@ -0,0 +1,318 @@
+//! Loading programs, as with exec().
+//!
+//! Each loadable segment of the ELF file becomes a private mapping of it,
+//! below the heap, so its pages are read from the file on first use, and
+//! the part of the segment past the data in the file, the BSS, starts out
+//! zeroed. Only the stack, which holds the arguments, is allocated up
+//! front. An executable too short for the segments it lists is rejected,
+//! since their pages couldn't be read once the old program is gone, and
+//! it can't be written while any of its segments are mapped.
+
+use super::{
+    limit::RLIMIT_AS,
+    mmap::{Mapping, MAP_PRIVATE, MMAP_BASE, NMAP, PROT_EXEC, PROT_READ, PROT_WRITE},
+    process::{Process, ProcessError},
+};
+use crate::{
+    elf::{
+        ElfHeader, ProgramHeader, ELF_MAGIC, ELF_PROG_FLAG_EXEC, ELF_PROG_FLAG_WRITE, ELF_PROG_LOAD,
+    },
+    fs::{
+        file::{filealloc, fileclose, filedup, File, FileType},
+        inode::{
+            ilock, ipermission, iunlock, iunlockput, namei, readi, Inode, InodeLockGuard, MAY_EXEC,
+        },
+        log::LogOperation,
+    },
+    hal::arch::{
+        mem::{round_up_page, Pagetable, PAGE_SIZE, PTE_W},
+        virtual_memory::{copyout, uvmalloc, uvmclear, walkaddr},
+    },
+    string::strlen,
+    MAXARG,
+};
+use core::{mem::size_of, ptr::addr_of_mut};
+
+/// A program being loaded into a new page table,
+/// which becomes the process's once it is all there.
+struct Image {
+    pagetable: Pagetable,
+    /// End of the segments and the stack, where the heap starts
+    size: u64,
+    /// The segments, which hold references to the executable
+    mappings: [Option<Mapping>; NMAP],
+    entry: u64,
+    stack_pointer: u64,
+    argc: usize,
+}
+impl Image {
+    /// Load the program at `path`, with the null-terminated
+    /// arguments `argv`, in no more than `limit` bytes.
+    unsafe fn load(
+        &mut self,
+        path: *mut u8,
+        argv: *const *mut u8,
+        limit: u64,
+    ) -> Result<(), ProcessError> {
+        let file = open_executable(path)?;
+        let entry = self.map_segments(file, limit);
+        // The segments hold their own references.
+        fileclose(file);
+        self.entry = entry?;
+        self.push_arguments(argv, limit)
+    }
+
+    /// Map the loadable segments of the executable `file`,
+    /// returning the address the program starts at.
+    unsafe fn map_segments(&mut self, file: *mut File, limit: u64) -> Result<u64, ProcessError> {
+        let ip = (*file).ip;
+        let _guard = InodeLockGuard::new(&mut *ip);
+
+        let mut header = ElfHeader::default();
+        if !read_struct(ip, &mut header, 0) || header.magic != ELF_MAGIC {
+            return Err(ProcessError::InvalidExecutable);
+        }
+        for i in 0..header.phnum as u64 {
+            let mut segment = ProgramHeader::default();
+            let offset = header.phoff + i * size_of::<ProgramHeader>() as u64;
+            if !read_struct(ip, &mut segment, offset) {
+                return Err(ProcessError::InvalidExecutable);
+            }
+            if segment.kind == ELF_PROG_LOAD {
+                self.map_segment(file, &segment, (*ip).size as u64, limit)?;
+            }
+        }
+        Ok(header.entry)
+    }
+
+    /// Map `segment` of the executable `file`, which is `file_size` bytes
+    /// long. Segments must come in order and start on a page boundary.
+    unsafe fn map_segment(
+        &mut self,
+        file: *mut File,
+        segment: &ProgramHeader,
+        file_size: u64,
+        limit: u64,
+    ) -> Result<(), ProcessError> {
+        let (Some(end), Some(file_end)) = (
+            segment.vaddr.checked_add(segment.memsz),
+            segment.off.checked_add(segment.filesz),
+        ) else {
+            return Err(ProcessError::InvalidExecutable);
+        };
+        if segment.memsz < segment.filesz
+            || segment.vaddr % PAGE_SIZE as u64 != 0
+            || segment.vaddr < self.size
+            || file_end > file_size
+        {
+            return Err(ProcessError::InvalidExecutable);
+        }
+        if end > limit {
+            return Err(ProcessError::Allocation);
+        }
+        if segment.memsz == 0 {
+            return Ok(());
+        }
+        let Some(slot) = self.mappings.iter().position(Option::is_none) else {
+            return Err(ProcessError::Allocation);
+        };
+
+        let mut prot = PROT_READ;
+        if segment.flags & ELF_PROG_FLAG_EXEC != 0 {
+            prot |= PROT_EXEC;
+        }
+        if segment.flags & ELF_PROG_FLAG_WRITE != 0 {
+            prot |= PROT_WRITE;
+        }
+        let end = round_up_page(end as usize) as u64;
+        self.mappings[slot] = Some(Mapping {
+            start: segment.vaddr,
+            end,
+            prot,
+            flags: MAP_PRIVATE,
+            file: filedup(file),
+            offset: segment.off,
+            file_end,
+        });
+        self.size = end;
+        Ok(())
+    }
+
+    /// Allocate the stack above the segments, with a guard page below
+    /// it, and push the arguments onto it for main(argc, argv).
+    unsafe fn push_arguments(
+        &mut self,
+        argv: *const *mut u8,
+        limit: u64,
+    ) -> Result<(), ProcessError> {
+        let top = round_up_page(self.size as usize) as u64 + 2 * PAGE_SIZE as u64;
+        if top > limit {
+            return Err(ProcessError::Allocation);
+        }
+        if uvmalloc(self.pagetable, self.size as usize, top as usize, PTE_W) == 0 {
+            return Err(ProcessError::Allocation);
+        }
+        self.size = top;
+        uvmclear(self.pagetable, (top - 2 * PAGE_SIZE as u64) as usize);
+        let stack_base = top - PAGE_SIZE as u64;
+
+        // The strings, then the array of pointers to them.
+        let mut sp = top;
+        let mut pointers = [0u64; MAXARG + 1];
+        let mut argc = 0;
+        while !(*argv.add(argc)).is_null() {
+            if argc >= MAXARG {
+                return Err(ProcessError::InvalidArgument);
+            }
+            let arg = *argv.add(argc);
+            let len = strlen(arg.cast()) as u64 + 1;
+            sp -= len;
+            // The RISC-V sp must be 16-byte aligned.
+            sp -= sp % 16;
+            if sp < stack_base || copyout(self.pagetable, sp as usize, arg, len as usize) < 0 {
+                return Err(ProcessError::Allocation);
+            }
+            pointers[argc] = sp;
+            argc += 1;
+        }
+
+        let len = (argc + 1) * size_of::<u64>();
+        sp -= len as u64;
+        sp -= sp % 16;
+        if sp < stack_base
+            || copyout(
+                self.pagetable,
+                sp as usize,
+                pointers.as_mut_ptr().cast(),
+                len,
+            ) < 0
+        {
+            return Err(ProcessError::Allocation);
+        }
+        self.stack_pointer = sp;
+        self.argc = argc;
+        Ok(())
+    }
+
+    /// Free a program that didn't load.
+    unsafe fn free(&mut self) {
+        for mapping in self.mappings.iter().flatten() {
+            fileclose(mapping.file);
+        }
+        Process::free_pagetable(self.pagetable, self.size as usize);
+    }
+}
+
+impl Process {
+    /// Replace the program the process runs with the one at `path`,
+    /// passing it the null-terminated `argv`. Returns the argument
+    /// count, which main() gets in a0.
+    pub unsafe fn exec(
+        &mut self,
+        path: *mut u8,
+        argv: *const *mut u8,
+    ) -> Result<usize, ProcessError> {
+        // Other threads are still running in this address space.
+        if self.thread_count() > 1 {
+            return Err(ProcessError::InvalidArgument);
+        }
+
+        // The program and its stack go below the mappings.
+        let limit = self.soft_limit(RLIMIT_AS).min(MMAP_BASE);
+        let mut image = Image {
+            pagetable: self.alloc_pagetable()?,
+            size: 0,
+            mappings: [None; NMAP],
+            entry: 0,
+            stack_pointer: 0,
+            argc: 0,
+        };
+        if let Err(error) = image.load(path, argv, limit) {
+            image.free();
+            return Err(error);
+        }
+
+        // Commit to the new program.
+        self.unmap_mappings();
+        let old_pagetable = self.pagetable;
+        let old_size = self.memory_allocated;
+        self.pagetable = image.pagetable;
+        self.memory_allocated = image.size;
+        self.heap_start = image.size;
+        self.mappings = image.mappings;
+        let trapframe = &mut *self.trapframe;
+        trapframe.epc = image.entry;
+        trapframe.sp = image.stack_pointer;
+        trapframe.a1 = image.stack_pointer;
+        Process::free_pagetable(old_pagetable, old_size as usize);
+
+        self.reset_signal_actions();
+        self.disarm_alarm();
+        self.exec_trace();
+        Ok(image.argc)
+    }
+
+    /// Read in every page of the program that hasn't been used yet.
+    pub unsafe fn load_program(&mut self) {
+        for i in 0..NMAP {
+            let Some(mapping) = self.mappings[i].filter(Mapping::is_program) else {
+                continue;
+            };
+            let mut va = mapping.start;
+            while va < mapping.end {
+                if walkaddr(self.pagetable, va as usize) == 0 {
+                    // Pages that can't be read in now are on first use.
+                    let _ = self.mapping_fault(va, PROT_READ);
+                }
+                va += PAGE_SIZE as u64;
+            }
+        }
+    }
+}
+
+/// Open the executable at `path` for reading its segments, if the
+/// process may run it. The file can't be written while this is open.
+unsafe fn open_executable(path: *mut u8) -> Result<*mut File, ProcessError> {
+    let _operation = LogOperation::new();
+    let ip = namei(path);
+    if ip.is_null() {
+        return Err(ProcessError::InvalidArgument);
+    }
+    ilock(ip);
+    if ipermission(ip, MAY_EXEC) < 0 {
+        iunlockput(ip);
+        return Err(ProcessError::PermissionDenied);
+    }
+
+    let file = filealloc();
+    if file.is_null() {
+        iunlockput(ip);
+        return Err(ProcessError::Allocation);
+    }
+    (*ip).text_references += 1;
+    iunlock(ip);
+    (*file).kind = FileType::Inode;
+    (*file).ip = ip;
+    (*file).off = 0;
+    (*file).readable = 1;
+    (*file).writable = 0;
+    (*file).text = 1;
+    Ok(file)
+}
+
+/// Read a `T` from the locked inode `ip` at `offset`,
+/// returning whether the file has all of it.
+unsafe fn read_struct<T>(ip: *mut Inode, value: &mut T, offset: u64) -> bool {
+    let size = size_of::<T>() as u32;
+    offset <= (u32::MAX - size) as u64
+        && readi(ip, 0, addr_of_mut!(*value) as u64, offset as u32, size) == size as i32
+}
+
+/// Run the program at `path`, for sys_exec() in sysfile.c.
+#[no_mangle]
+pub unsafe extern "C" fn exec(path: *mut u8, argv: *const *mut u8) -> i32 {
+    match Process::current().unwrap().exec(path, argv) {
+        Ok(argc) => argc as i32,
+        Err(_) => -1,
+    }
+}
@@ -0,0 +1,125 @@
+//! Resource limits, as set by setrlimit().
+//!
+//! Each limit has a soft value, which is enforced, and a hard value,
+//! which is the most the soft value can be raised to. Limits are kept by
+//! the thread group leader and copied by fork(). A process can lower
+//! its hard limits, but only root can raise them again.
+
+use super::{
+    process::{Process, ProcessError, ProcessState},
+    signal::{SIGKILL, SIGXCPU},
+    table::PROCESSES,
+};
+use core::ptr::addr_of_mut;
+
+/// Timer ticks of CPU time. SIGXCPU is sent every tick the process
+/// runs past the soft limit, and SIGKILL once it reaches the hard limit.
+pub const RLIMIT_CPU: usize = 0;
+/// Bytes of user memory, checked by sbrk(), mmap() and exec().
+pub const RLIMIT_AS: usize = 1;
+/// One more than the highest file descriptor that can be opened.
+pub const RLIMIT_NOFILE: usize = 2;
+/// Number of children a process can have at once.
+pub const RLIMIT_NPROC: usize = 3;
+/// Bytes of core file written when a fault kills the process,
+/// with 0 turning core dumps off.
+pub const RLIMIT_CORE: usize = 4;
+/// Number of kinds of limit.
+pub const RLIMIT_COUNT: usize = 5;
+
+/// A limit that isn't enforced.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// One limit, as used by getrlimit() and setrlimit().
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResourceLimit {
+    /// Soft limit, which is enforced
+    pub current: u64,
+    /// Hard limit, the ceiling for the soft limit
+    pub max: u64,
+}
+impl ResourceLimit {
+    pub const fn unlimited() -> ResourceLimit {
+        ResourceLimit {
+            current: RLIM_INFINITY,
+            max: RLIM_INFINITY,
+        }
+    }
+    /// The limits init starts with, which nothing
+    /// but core dumps is held to.
+    pub const fn defaults() -> [ResourceLimit; RLIMIT_COUNT] {
+        let mut limits = [ResourceLimit::unlimited(); RLIMIT_COUNT];
+        limits[RLIMIT_CORE].current = 0;
+        limits
+    }
+}
+
+impl Process {
+    pub fn limit(&mut self, resource: usize) -> Result<ResourceLimit, ProcessError> {
+        self.leader()
+            .limits
+            .get(resource)
+            .copied()
+            .ok_or(ProcessError::InvalidArgument)
+    }
+    /// Change a limit, returning the old one.
+    /// The soft limit can't be above the hard limit,
+    /// and only root can raise the hard limit.
+    pub fn set_limit(
+        &mut self,
+        resource: usize,
+        limit: ResourceLimit,
+    ) -> Result<ResourceLimit, ProcessError> {
+        let old_limit = self.limit(resource)?;
+
+        if limit.current > limit.max {
+            return Err(ProcessError::InvalidArgument);
+        } else if limit.max > old_limit.max && !self.is_privileged() {
+            return Err(ProcessError::PermissionDenied);
+        }
+
+        self.leader().limits[resource] = limit;
+        Ok(old_limit)
+    }
+    /// The soft limit on a resource, for enforcing it.
+    pub fn soft_limit(&mut self, resource: usize) -> u64 {
+        self.leader().limits[resource].current
+    }
+
+    /// Whether the process can have another child.
+    pub unsafe fn can_fork(&mut self) -> bool {
+        let limit = self.soft_limit(RLIMIT_NPROC);
+        if limit == RLIM_INFINITY {
+            return true;
+        }
+
+        let parent = addr_of_mut!(*self);
+        let children = PROCESSES
+            .iter()
+            .filter(|p| p.parent == parent && !p.is_thread() && p.state != ProcessState::Unused)
+            .count();
+        (children as u64) < limit
+    }
+
+    /// Signal the process if it has run past its CPU time limit.
+    /// Called at each timer interrupt while it is running.
+    pub unsafe fn check_cpu_limit(&mut self) {
+        let limit = self.leader().limits[RLIMIT_CPU];
+        let used = self.user_ticks + self.system_ticks;
+
+        if used >= limit.max {
+            self.set_killed(SIGKILL);
+        } else if used >= limit.current {
+            self.lock.lock_unguarded();
+            self.send_signal(SIGXCPU);
+            self.lock.unlock();
+        }
+    }
+}
+
+/// The file descriptor limit of a process, for fdalloc() in sysfile.c.
+#[no_mangle]
+pub unsafe extern "C" fn proc_filelimit(p: *mut Process) -> i32 {
+    (*p).soft_limit(RLIMIT_NOFILE).min(crate::NOFILE as u64) as i32
+}
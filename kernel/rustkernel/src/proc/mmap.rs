@@ -0,0 +1,552 @@
+//! Memory mappings, as made by mmap().
+//!
+//! Mappings are placed top down between MMAP_BASE and the thread
+//! trapframes, out of the way of the heap below, and their pages are
+//! allocated on first use. A mapping is either anonymous and private,
+//! starting out zeroed, or of a file, read from it as pages are used.
+//! They are kept by the thread group leader and copied by fork(),
+//! copy-on-write unless they are shared. exec() maps the program's
+//! segments the same way, below the heap.
+//!
+//! Pages of a shared file mapping are mapped read-only until they are
+//! written, and the dirty ones are written back to the file by munmap(),
+//! msync(), exit() and exec(). Each process reads its own copy of the
+//! file, so other processes only see the changes once written back.
+
+use super::{
+    limit::RLIMIT_AS,
+    process::{Process, ProcessError},
+};
+use crate::{
+    fs::{
+        file::{fileclose, filedup, File, FileType},
+        inode::{readi, writei, InodeLockGuard},
+        log::LogOperation,
+        BSIZE,
+    },
+    hal::arch::{
+        mem::{
+            round_down_page, round_up_page, thread_trapframe, Pagetable, PAGE_SIZE, PTE_R, PTE_W,
+            PTE_X,
+        },
+        virtual_memory::{uvmclean, uvmcopy, uvmdirty, uvminstall, uvmunmap},
+    },
+    mem::{
+        kalloc::{kalloc, kfree},
+        memset,
+    },
+    MAXOPBLOCKS, NOFILE, NPROC,
+};
+use core::ptr::{addr_of_mut, null_mut};
+
+/// Memory protection, as passed to mmap().
+pub const PROT_READ: i32 = 1;
+pub const PROT_WRITE: i32 = 2;
+pub const PROT_EXEC: i32 = 4;
+
+/// mmap() flags.
+pub const MAP_SHARED: i32 = 0x01;
+pub const MAP_PRIVATE: i32 = 0x02;
+pub const MAP_ANONYMOUS: i32 = 0x20;
+
+/// Most mappings a process can have at once.
+pub const NMAP: usize = 16;
+
+/// Lowest address of a mapping, which the heap can't grow past.
+pub const MMAP_BASE: u64 = 1 << 37;
+
+/// End of the region mappings are placed in.
+fn mmap_end() -> u64 {
+    thread_trapframe(NPROC - 1) as u64
+}
+
+/// A range of user memory mapped by mmap().
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mapping {
+    /// Page-aligned start address
+    pub start: u64,
+    /// Page-aligned end address
+    pub end: u64,
+    /// PROT_* bits
+    pub prot: i32,
+    /// MAP_* bits
+    pub flags: i32,
+    /// The mapped file, which the mapping holds
+    /// a reference to, or null for anonymous memory
+    pub file: *mut File,
+    /// Offset in the file of the first page
+    pub offset: u64,
+    /// Offset in the file where the mapped data ends,
+    /// past which pages are left zeroed
+    pub file_end: u64,
+}
+impl Mapping {
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+    /// Whether changes are written back to the file.
+    pub fn is_shared(&self) -> bool {
+        !self.file.is_null() && self.flags & MAP_SHARED != 0
+    }
+    /// Whether this is a segment of the program, which is part
+    /// of `memory_allocated` rather than the mmap() region.
+    pub fn is_program(&self) -> bool {
+        self.start < MMAP_BASE
+    }
+    /// Page table permissions for the mapping's pages.
+    fn permissions(&self) -> i32 {
+        let mut perm = 0;
+        if self.prot & PROT_READ != 0 {
+            perm |= PTE_R;
+        }
+        if self.prot & PROT_WRITE != 0 {
+            perm |= PTE_W;
+        }
+        if self.prot & PROT_EXEC != 0 {
+            perm |= PTE_X;
+        }
+        perm
+    }
+    /// Offset in the file of the page at `addr`.
+    fn file_offset(&self, addr: u64) -> u64 {
+        self.offset + (addr - self.start)
+    }
+}
+
+impl Process {
+    /// Map `length` bytes of new memory, returning its address.
+    ///
+    /// Unless `flags` has MAP_ANONYMOUS, the memory holds the open file
+    /// `file_descriptor` from `offset` on, which must be page-aligned.
+    pub unsafe fn mmap(
+        &mut self,
+        length: u64,
+        prot: i32,
+        flags: i32,
+        file_descriptor: i32,
+        offset: u64,
+    ) -> Result<u64, ProcessError> {
+        if length == 0
+            || length > mmap_end() - MMAP_BASE
+            || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+        {
+            return Err(ProcessError::InvalidArgument);
+        }
+        let length = round_up_page(length as usize) as u64;
+
+        let file = if flags == MAP_PRIVATE | MAP_ANONYMOUS {
+            null_mut()
+        } else if flags == MAP_PRIVATE || flags == MAP_SHARED {
+            self.mappable_file(file_descriptor, prot, flags, offset)?
+        } else {
+            return Err(ProcessError::InvalidArgument);
+        };
+
+        let leader = addr_of_mut!(*self.leader());
+        let _guard = (*leader).lock.lock();
+        if (*leader).memory_allocated + (*leader).mapped_bytes() + length
+            > self.soft_limit(RLIMIT_AS)
+        {
+            return Err(ProcessError::Allocation);
+        }
+        let Some(slot) = (*leader).mappings.iter().position(Option::is_none) else {
+            return Err(ProcessError::Allocation);
+        };
+        let start = (*leader).find_space(length)?;
+
+        if !file.is_null() {
+            filedup(file);
+        }
+        (*leader).mappings[slot] = Some(Mapping {
+            start,
+            end: start + length,
+            prot,
+            flags,
+            file,
+            offset,
+            file_end: offset + length,
+        });
+        Ok(start)
+    }
+
+    /// The open file `file_descriptor`, if it can be
+    /// mapped from `offset` with `prot` and `flags`.
+    unsafe fn mappable_file(
+        &mut self,
+        file_descriptor: i32,
+        prot: i32,
+        flags: i32,
+        offset: u64,
+    ) -> Result<*mut File, ProcessError> {
+        if file_descriptor < 0
+            || file_descriptor as usize >= NOFILE
+            || offset % PAGE_SIZE as u64 != 0
+            || offset > u32::MAX as u64
+        {
+            return Err(ProcessError::InvalidArgument);
+        }
+
+        let file = self.leader().open_files[file_descriptor as usize];
+        if file.is_null() {
+            return Err(ProcessError::InvalidArgument);
+        }
+        if (*file).kind != FileType::Inode
+            || (*file).readable == 0
+            || (flags == MAP_SHARED && prot & PROT_WRITE != 0 && (*file).writable == 0)
+        {
+            return Err(ProcessError::PermissionDenied);
+        }
+        Ok(file)
+    }
+
+    /// Find the highest free range of `length` bytes for a mapping.
+    fn find_space(&self, length: u64) -> Result<u64, ProcessError> {
+        let mut end = mmap_end();
+        'search: while end - MMAP_BASE >= length {
+            for mapping in self.mappings.iter().flatten() {
+                if mapping.overlaps(end - length, end) {
+                    end = mapping.start;
+                    continue 'search;
+                }
+            }
+            return Ok(end - length);
+        }
+        Err(ProcessError::Allocation)
+    }
+
+    /// Bytes of address space taken up by mmap(), leaving out
+    /// the program's segments, which `memory_allocated` counts.
+    pub fn mapped_bytes(&self) -> u64 {
+        self.mappings
+            .iter()
+            .flatten()
+            .filter(|mapping| !mapping.is_program())
+            .map(|mapping| mapping.end - mapping.start)
+            .sum()
+    }
+
+    /// Unmap the pages from `addr` to `addr + length`, shrinking or
+    /// splitting the mappings they were part of.
+    pub unsafe fn munmap(&mut self, addr: u64, length: u64) -> Result<(), ProcessError> {
+        let end = mapped_range(addr, length)?;
+        self.sync_mappings(addr, end)?;
+
+        let pagetable = self.pagetable;
+        let leader = addr_of_mut!(*self.leader());
+        let mut closing = [null_mut(); NMAP];
+        {
+            let _guard = (*leader).lock.lock();
+            let mappings = &mut (*leader).mappings;
+
+            // Splitting a mapping in two takes another slot.
+            let splits = mappings
+                .iter()
+                .flatten()
+                .any(|m| m.start < addr && end < m.end);
+            if splits && !mappings.contains(&None) {
+                return Err(ProcessError::Allocation);
+            }
+
+            for i in 0..NMAP {
+                let Some(mapping) = mappings[i] else {
+                    continue;
+                };
+                if !mapping.overlaps(addr, end) {
+                    continue;
+                }
+
+                // The parts that stay mapped.
+                let below = Mapping {
+                    end: addr,
+                    ..mapping
+                };
+                let above = Mapping {
+                    start: end,
+                    offset: mapping.file_offset(end),
+                    ..mapping
+                };
+                let below = (below.start < below.end).then_some(below);
+                let above = (above.start < above.end).then_some(above);
+
+                if below.is_some() && above.is_some() {
+                    let slot = mappings.iter().position(Option::is_none).unwrap();
+                    if !mapping.file.is_null() {
+                        filedup(mapping.file);
+                    }
+                    mappings[slot] = above;
+                } else if below.is_none() && above.is_none() {
+                    closing[i] = mapping.file;
+                }
+
+                let start = mapping.start.max(addr);
+                let stop = mapping.end.min(end);
+                unmap_range(pagetable, start, stop);
+                mappings[i] = below.or(above);
+            }
+        }
+
+        // Closing a file can write to the disk,
+        // so wait until the lock is released.
+        for file in closing {
+            if !file.is_null() {
+                fileclose(file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the dirty pages from `addr` to `addr + length` of shared
+    /// file mappings back to their files, as with msync().
+    pub unsafe fn msync(&mut self, addr: u64, length: u64) -> Result<(), ProcessError> {
+        let end = mapped_range(addr, length)?;
+        self.sync_mappings(addr, end)
+    }
+
+    /// Write the dirty pages from `start` to `end` of shared file mappings
+    /// back to their files, while other threads may be changing them.
+    unsafe fn sync_mappings(&mut self, start: u64, end: u64) -> Result<(), ProcessError> {
+        let pagetable = self.pagetable;
+        let leader = addr_of_mut!(*self.leader());
+
+        // Writing can sleep, so work on copies of the
+        // mappings, with their own references to the files.
+        let mut mappings = [None; NMAP];
+        {
+            let _guard = (*leader).lock.lock();
+            for (copy, mapping) in mappings.iter_mut().zip((*leader).mappings.iter()) {
+                if let Some(m) = mapping.filter(|m| m.is_shared() && m.overlaps(start, end)) {
+                    filedup(m.file);
+                    *copy = Some(m);
+                }
+            }
+        }
+
+        let mut result = Ok(());
+        for mapping in mappings.iter().flatten() {
+            let from = mapping.start.max(start);
+            let to = mapping.end.min(end);
+            result = result.and(write_back(pagetable, mapping, from, to));
+            fileclose(mapping.file);
+        }
+        result
+    }
+
+    /// Unmap all the process's mappings, writing back dirty
+    /// pages of shared file mappings and closing their files.
+    ///
+    /// The caller must be the only thread. This sleeps
+    /// unless the process has never run.
+    pub unsafe fn unmap_mappings(&mut self) {
+        for mapping in self.mappings.iter_mut() {
+            if let Some(m) = mapping.take() {
+                if m.is_shared() {
+                    // There's no one left to report a failure to.
+                    let _ = write_back(self.pagetable, &m, m.start, m.end);
+                }
+                unmap_range(self.pagetable, m.start, m.end);
+                if !m.file.is_null() {
+                    fileclose(m.file);
+                }
+            }
+        }
+    }
+
+    /// Copy the mappings of `parent`'s thread group into this new process.
+    pub unsafe fn copy_mappings(&mut self, parent: &mut Process) -> Result<(), ProcessError> {
+        let pagetable = parent.pagetable;
+        let leader = addr_of_mut!(*parent.leader());
+        let _guard = (*leader).lock.lock();
+
+        for (i, mapping) in (*leader).mappings.iter().enumerate() {
+            if let Some(m) = mapping {
+                // fork() copies the program's pages with the rest of memory.
+                if !m.is_program()
+                    && uvmcopy(
+                        pagetable,
+                        self.pagetable,
+                        m.start as usize,
+                        m.end as usize,
+                        !m.is_shared(),
+                    ) < 0
+                {
+                    return Err(ProcessError::Allocation);
+                }
+                if !m.file.is_null() {
+                    filedup(m.file);
+                }
+                self.mappings[i] = Some(*m);
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocate a page for `addr` on first use, if it is in a mapping
+    /// that allows `access`, made of PROT_* bits, reading it from the
+    /// mapped file if there is one. A write to a clean page of a
+    /// shared file mapping marks it dirty.
+    pub unsafe fn mapping_fault(&mut self, addr: u64, access: i32) -> Result<(), ProcessError> {
+        let pagetable = self.pagetable;
+        let leader = addr_of_mut!(*self.leader());
+        let va = round_down_page(addr as usize);
+
+        let mapping = {
+            let _guard = (*leader).lock.lock();
+            let Some(mapping) = (*leader)
+                .mappings
+                .iter()
+                .flatten()
+                .find(|m| m.contains(addr))
+            else {
+                return Err(ProcessError::PageError);
+            };
+            // Pages can't be mapped without read access.
+            if mapping.prot & (access | PROT_READ) != access | PROT_READ {
+                return Err(ProcessError::PermissionDenied);
+            }
+
+            let writing = mapping.is_shared() && access & PROT_WRITE != 0;
+            if writing && uvmdirty(pagetable, va) == 0 {
+                return Ok(());
+            }
+            if !mapping.file.is_null() {
+                // Keep the file open while reading it.
+                filedup(mapping.file);
+            }
+            *mapping
+        };
+
+        let page = kalloc();
+        let mut read = true;
+        if !page.is_null() {
+            memset(page, 0, PAGE_SIZE);
+            if !mapping.file.is_null() {
+                read = read_page(&mapping, page, va as u64);
+            }
+        }
+        if !mapping.file.is_null() {
+            fileclose(mapping.file);
+        }
+        if page.is_null() {
+            return Err(ProcessError::Allocation);
+        }
+        if !read {
+            kfree(page);
+            return Err(ProcessError::BusError);
+        }
+
+        let _guard = (*leader).lock.lock();
+        // The mapping may have changed while the file was read.
+        let still_mapped = (*leader).mappings.iter().flatten().any(|m| {
+            m.contains(addr)
+                && m.file == mapping.file
+                && m.file_offset(va as u64) == mapping.file_offset(va as u64)
+                && m.file_end == mapping.file_end
+                && m.prot == mapping.prot
+                && m.flags == mapping.flags
+        });
+        if !still_mapped {
+            kfree(page);
+            return Err(ProcessError::PageError);
+        }
+
+        let mut perm = mapping.permissions();
+        if mapping.is_shared() {
+            perm &= !PTE_W;
+        }
+        if uvminstall(pagetable, va, page, perm) != 0 {
+            return Err(ProcessError::Allocation);
+        }
+        if mapping.is_shared() && access & PROT_WRITE != 0 && uvmdirty(pagetable, va) != 0 {
+            return Err(ProcessError::PageError);
+        }
+        Ok(())
+    }
+}
+
+/// Check the range passed to munmap() or
+/// msync(), returning the page-aligned end of it.
+fn mapped_range(addr: u64, length: u64) -> Result<u64, ProcessError> {
+    if addr % PAGE_SIZE as u64 != 0
+        || length == 0
+        || addr < MMAP_BASE
+        || addr > mmap_end()
+        || length > mmap_end() - addr
+    {
+        return Err(ProcessError::InvalidArgument);
+    }
+    Ok(round_up_page((addr + length) as usize) as u64)
+}
+
+/// Unmap the pages of a mapping from `start` to `end` that have been used.
+unsafe fn unmap_range(pagetable: Pagetable, start: u64, end: u64) {
+    uvmunmap(
+        pagetable,
+        start as usize,
+        ((end - start) / PAGE_SIZE as u64) as usize,
+        true,
+    );
+}
+
+/// Fill `page` with the data `mapping` has for the page at `va`, leaving
+/// any part past the end of it, or of the file, zeroed. Returns false
+/// if the page is wholly past the end of the file, as when the file has
+/// been truncated since it was mapped, or it couldn't be read.
+unsafe fn read_page(mapping: &Mapping, page: *mut u8, va: u64) -> bool {
+    let offset = mapping.file_offset(va);
+    if offset >= mapping.file_end || offset > u32::MAX as u64 {
+        return true;
+    }
+    let n = (mapping.file_end - offset).min(PAGE_SIZE as u64) as u32;
+    let ip = (*mapping.file).ip;
+    let _guard = InodeLockGuard::new(&mut *ip);
+    readi(ip, 0, page as u64, offset as u32, n) > 0
+}
+
+/// Write the dirty pages of `mapping` from `start` to `end` back to its file.
+unsafe fn write_back(
+    pagetable: Pagetable,
+    mapping: &Mapping,
+    start: u64,
+    end: u64,
+) -> Result<(), ProcessError> {
+    let mut result = Ok(());
+    let mut va = start;
+    while va < end {
+        let pa = uvmclean(pagetable, va as usize);
+        if pa != 0 {
+            result = result.and(write_page(mapping.file, pa, mapping.file_offset(va)));
+        }
+        va += PAGE_SIZE as u64;
+    }
+    result
+}
+
+/// Write the page at `pa` to the file at `offset`, a few blocks at a
+/// time as filewrite() does. Mappings don't make the file any bigger,
+/// so any part of the page past its end is left out.
+unsafe fn write_page(file: *mut File, pa: u64, offset: u64) -> Result<(), ProcessError> {
+    let max = ((MAXOPBLOCKS - 1 - 1 - 2) / 2) as u64 * BSIZE as u64;
+    let ip = (*file).ip;
+    let mut written = 0;
+
+    while written < PAGE_SIZE as u64 {
+        let _operation = LogOperation::new();
+        let _guard = InodeLockGuard::new(&mut *ip);
+
+        let size = (*ip).size as u64;
+        if offset + written >= size {
+            break;
+        }
+        let n = (PAGE_SIZE as u64 - written)
+            .min(max)
+            .min(size - offset - written) as u32;
+        if writei(ip, 0, pa + written, (offset + written) as u32, n) != n as i32 {
+            return Err(ProcessError::PageError);
+        }
+        written += n as u64;
+    }
+    Ok(())
+}
@@ -1,5 +1,15 @@
+pub mod alarm;
 pub mod context;
+pub mod coredump;
 pub mod cpu;
+pub mod credentials;
+pub mod exec;
+pub mod limit;
+pub mod mmap;
 pub mod process;
+pub mod ptrace;
+pub mod realtime;
 pub mod scheduler;
+pub mod signal;
+pub mod table;
 pub mod trapframe;
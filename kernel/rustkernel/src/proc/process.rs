@@ -2,8 +2,14 @@
 
 use super::{
     context::Context,
+    coredump::dumps_core,
     cpu::Cpu,
-    scheduler::{sched, wakeup},
+    limit::{ResourceLimit, RLIMIT_AS, RLIMIT_COUNT},
+    mmap::{Mapping, MMAP_BASE, NMAP, PROT_EXEC},
+    realtime::{self, Reservation},
+    scheduler::{cpu_allowed, make_runnable, remove_runnable, sched, wakeup, ALL_CPUS, RUN_QUEUES},
+    signal::{SignalAction, NSIG, SIGCHLD, SIGKILL},
+    table::PROCESSES,
     trapframe::Trapframe,
 };
 use crate::{
@@ -15,21 +21,21 @@ use crate::{
         FS_INITIALIZED,
     },
     hal::arch::{
-        mem::{kstack, Pagetable, PAGE_SIZE, PTE_R, PTE_W, PTE_X, TRAMPOLINE, TRAPFRAME},
+        clock::uptime,
+        mem::{thread_trapframe, Pagetable, PAGE_SIZE, PTE_R, PTE_W, PTE_X, TRAMPOLINE, TRAPFRAME},
         trap::{usertrapret, InterruptBlocker},
         virtual_memory::{
-            copyout, mappages, uvmalloc, uvmcopy, uvmcreate, uvmdealloc, uvmfirst, uvmfree,
-            uvmunmap,
+            copyout, mappages, uvmcopy, uvmcreate, uvmdealloc, uvmfirst, uvmfree, uvmlazy, uvmunmap,
         },
     },
     mem::{
         kalloc::{kalloc, kfree},
         memset,
     },
+    println,
     sync::spinlock::Spinlock,
-    uprintln,
+    uprintln, NCPU,
 };
-use arrayvec::ArrayVec;
 use core::{
     ffi::{c_char, c_void, CStr},
     ptr::{addr_of, addr_of_mut, null_mut},
@@ -48,24 +54,25 @@ pub static NEXT_PID: AtomicI32 = AtomicI32::new(1);
 /// Must be acquired before any p->lock.
 pub static mut WAIT_LOCK: Spinlock = Spinlock::new();
 pub static mut INITPROC: usize = 0;
-pub static mut PROCESSES: ArrayVec<Process, { crate::NPROC }> = ArrayVec::new_const();
-
-/// Initialize the proc table.
-pub unsafe fn procinit() {
-    let mut i = 0;
-    let processes_iter = core::iter::repeat_with(|| {
-        let mut p = Process::new();
-        p.state = ProcessState::Unused;
-        p.kernel_stack = kstack(i) as u64;
-        i += 1;
-        p
-    });
-    PROCESSES = processes_iter.take(crate::NPROC).collect();
-}
+
+/// waitpid() option to return immediately if no child has exited.
+pub const WNOHANG: i32 = 1;
+/// waitpid() option to also report children that have stopped.
+pub const WUNTRACED: i32 = 2;
+
+/// Most favorable scheduling priority.
+pub const PRIORITY_HIGHEST: i32 = 0;
+/// Least favorable scheduling priority.
+pub const PRIORITY_LOWEST: i32 = 39;
+/// Scheduling priority of the first process.
+pub const PRIORITY_DEFAULT: i32 = 20;
+
 /// Set up the first user process.
 pub unsafe fn userinit() {
     let p = Process::alloc().unwrap();
     INITPROC = addr_of_mut!(*p) as usize;
+    p.pgid = p.pid;
+    p.sid = p.pid;
 
     let initcode: &[u8] = &[
         0x17, 0x05, 0x00, 0x00, 0x13, 0x05, 0x45, 0x02, 0x97, 0x05, 0x00, 0x00, 0x93, 0x85, 0x35,
@@ -78,6 +85,7 @@ pub unsafe fn userinit() {
     // instructions and data into it.
     uvmfirst(p.pagetable, initcode.as_ptr().cast_mut(), initcode.len());
     p.memory_allocated = PAGE_SIZE as u64;
+    p.heap_start = PAGE_SIZE as u64;
 
     // Prepare for the very first "return" from kernel to user.
     // User program counter
@@ -92,7 +100,7 @@ pub unsafe fn userinit() {
             .cast_mut()
             .cast(),
     );
-    p.state = ProcessState::Runnable;
+    make_runnable(p);
     p.lock.unlock();
 }
 
@@ -106,6 +114,22 @@ pub enum ProcessState {
     Runnable,
     Running,
     Zombie,
+    Stopped,
+}
+
+/// CPU time used by a process and its waited-for children,
+/// as returned by the times() system call.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Times {
+    /// Timer ticks spent in user mode.
+    pub user_ticks: u64,
+    /// Timer ticks spent in the kernel.
+    pub system_ticks: u64,
+    /// User ticks of waited-for children.
+    pub children_user_ticks: u64,
+    /// System ticks of waited-for children.
+    pub children_system_ticks: u64,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -115,6 +139,12 @@ pub enum ProcessError {
     NoChildren,
     Killed,
     PageError,
+    NoSuchProcess,
+    InvalidArgument,
+    Oversubscribed,
+    PermissionDenied,
+    InvalidExecutable,
+    BusError,
 }
 
 /// Per-process state.
@@ -128,7 +158,7 @@ pub struct Process {
     pub state: ProcessState,
     /// If non-zero, sleeping on chan
     pub chan: *mut c_void,
-    /// If non-zero, have been killed
+    /// If non-zero, have been killed, by this signal
     pub killed: i32,
     /// Exit status to be returned to parent's wait
     pub exit_status: i32,
@@ -154,6 +184,94 @@ pub struct Process {
     pub open_files: [*mut File; crate::NOFILE],
     /// Current directory
     pub current_dir: *mut Inode,
+
+    // p->lock must be held when using these:
+    /// CPU whose run queue this process is placed on
+    pub cpu: usize,
+    /// Timer ticks left before this process is preempted
+    pub time_slice: usize,
+    /// Scheduling priority, lower is more favorable
+    pub priority: i32,
+    /// CPU time used, weighted by priority
+    pub vruntime: u64,
+    /// Real-time CPU reservation, if any
+    pub reservation: Reservation,
+    /// Harts this process may run on, one bit each
+    pub affinity: u64,
+
+    // These are private to the process, so p->lock need not be held.
+    /// Timer ticks spent running in user mode
+    pub user_ticks: u64,
+    /// Timer ticks spent running in the kernel
+    pub system_ticks: u64,
+    /// User ticks of waited-for children and their descendants
+    pub children_user_ticks: u64,
+    /// System ticks of waited-for children and their descendants
+    pub children_system_ticks: u64,
+    /// Process whose address space, open files and current directory
+    /// this thread shares, or null if this is not a thread.
+    /// Set once when the thread is created.
+    pub group_leader: *mut Process,
+    /// User virtual address of the trapframe
+    pub trapframe_va: u64,
+    /// User stack passed to clone(), handed back by join()
+    pub user_stack: u64,
+    /// Resource limits, which only the group leader's are used
+    pub limits: [ResourceLimit; RLIMIT_COUNT],
+    /// User ID, which only the group leader's is used
+    pub uid: u32,
+    /// Group ID, which only the group leader's is used
+    pub gid: u32,
+    /// Syscalls to print as they return, one bit per syscall number
+    pub trace_mask: u64,
+    /// Whether a fault writes a core file, which only the group leader's is used
+    pub dumpable: bool,
+    /// Start of the heap, which sbrk() grows and pages are allocated
+    /// for on first use. The heap ends at memory_allocated.
+    pub heap_start: u64,
+    /// Memory mapped by mmap(), which only the group leader's is used
+    pub mappings: [Option<Mapping>; NMAP],
+
+    // p->lock must be held when using these:
+    /// Signals sent but not yet delivered
+    pub pending_signals: u32,
+    /// Signals whose delivery is postponed
+    pub blocked_signals: u32,
+    /// What to do when each signal is delivered
+    pub signal_actions: [SignalAction; NSIG],
+    /// Where signal handlers return to, to call sigreturn()
+    pub signal_restorer: u64,
+    /// Process group ID
+    pub pgid: i32,
+    /// Session ID
+    pub sid: i32,
+    /// Signal that stopped the process, until reported by waitpid()
+    pub stop_signal: i32,
+    /// Tick at which the interval timer next expires, or 0 if it is disarmed
+    pub alarm_deadline: usize,
+    /// Ticks between expirations of the interval timer, or 0 if it only expires once
+    pub alarm_interval: usize,
+    /// Whether the parent is tracing this process with ptrace()
+    pub traced: bool,
+    /// Signal for a traced process to take once its tracer continues it
+    pub resume_signal: usize,
+    /// Address of the breakpoint set by a single step, if there is one
+    pub step_address: Option<u64>,
+    /// The instruction the single step's breakpoint replaced
+    pub step_instruction: u16,
+
+    // These are set up by the process table.
+    /// Position in the process table, which picks the
+    /// kernel stack and any thread trapframe address
+    pub index: usize,
+    /// Next process in the process table
+    pub table_next: *mut Process,
+    /// Next process with a pid in the same bucket of the pid index
+    pub pid_next: *mut Process,
+
+    // The run queue's lock must be held when using this:
+    /// Next process on the same run queue
+    pub run_next: *mut Process,
 }
 impl Process {
     pub const fn new() -> Process {
@@ -172,6 +290,43 @@ impl Process {
             context: Context::new(),
             open_files: [null_mut(); crate::NOFILE],
             current_dir: null_mut(),
+            cpu: 0,
+            time_slice: 0,
+            priority: PRIORITY_DEFAULT,
+            vruntime: 0,
+            reservation: Reservation::none(),
+            affinity: ALL_CPUS,
+            user_ticks: 0,
+            system_ticks: 0,
+            children_user_ticks: 0,
+            children_system_ticks: 0,
+            group_leader: null_mut(),
+            trapframe_va: TRAPFRAME as u64,
+            user_stack: 0,
+            limits: ResourceLimit::defaults(),
+            uid: 0,
+            gid: 0,
+            trace_mask: 0,
+            dumpable: true,
+            heap_start: 0,
+            mappings: [None; NMAP],
+            pending_signals: 0,
+            blocked_signals: 0,
+            signal_actions: [SignalAction::new(); NSIG],
+            signal_restorer: 0,
+            pgid: 0,
+            sid: 0,
+            stop_signal: 0,
+            alarm_deadline: 0,
+            alarm_interval: 0,
+            traced: false,
+            resume_signal: 0,
+            step_address: None,
+            step_instruction: 0,
+            index: 0,
+            table_next: null_mut(),
+            pid_next: null_mut(),
+            run_next: null_mut(),
         }
     }
     pub fn current() -> Option<&'static mut Process> {
@@ -189,32 +344,58 @@ impl Process {
     pub fn is_initproc(&self) -> bool {
         addr_of!(*self) as usize == unsafe { INITPROC }
     }
+    pub fn is_thread(&self) -> bool {
+        !self.group_leader.is_null()
+    }
+    /// The process that owns this thread's address space,
+    /// open files and current directory.
+    pub fn leader(&mut self) -> &mut Process {
+        if self.is_thread() {
+            unsafe { &mut *self.group_leader }
+        } else {
+            self
+        }
+    }
+    /// Number of threads, including the leader, sharing this thread's address space.
+    pub unsafe fn thread_count(&mut self) -> usize {
+        let leader = addr_of_mut!(*self.leader());
+        PROCESSES
+            .iter()
+            .filter(|p| {
+                p.state != ProcessState::Unused
+                    && (addr_of!(**p).cast_mut() == leader || p.group_leader == leader)
+            })
+            .count()
+    }
 
     pub fn alloc_pid() -> i32 {
         NEXT_PID.fetch_add(1, Ordering::SeqCst)
     }
-    /// Look in the process table for an UNUSED proc.
+    /// Look in the process table for an UNUSED proc, or add one.
     /// If found, initialize state required to run in the kernel,
     /// and return with p.lock held.
-    /// If there are no free procs, or a memory allocation fails, return an error.
+    /// If there are already as many procs as allowed,
+    /// or a memory allocation fails, return an error.
     pub unsafe fn alloc() -> Result<&'static mut Process, ProcessError> {
-        let mut index: Option<usize> = None;
-        for (i, p) in PROCESSES.iter_mut().enumerate() {
+        PROCESSES.reserve()?;
+
+        let unused = PROCESSES.iter_mut().find(|p| {
             p.lock.lock_unguarded();
             if p.state == ProcessState::Unused {
-                index = Some(i);
-                break;
+                true
             } else {
                 p.lock.unlock();
+                false
             }
-        }
-        let Some(index) = index else {
-            return Err(ProcessError::MaxProcesses);
+        });
+        let p = match unused {
+            Some(p) => p,
+            None => PROCESSES.grow().inspect_err(|_| PROCESSES.unreserve())?,
         };
 
-        let p: &mut Process = &mut PROCESSES[index];
         p.pid = Process::alloc_pid();
         p.state = ProcessState::Used;
+        PROCESSES.insert(p);
 
         // Allocate a trapframe page.
         p.trapframe = kalloc() as *mut Trapframe;
@@ -248,36 +429,77 @@ impl Process {
     /// Free a proc structure and the data hanging from it, including user pages.
     /// self.lock must be held.
     pub unsafe fn free(&mut self) {
+        if self.state != ProcessState::Unused {
+            PROCESSES.remove(self);
+        }
         if !self.trapframe.is_null() {
             kfree(self.trapframe.cast());
         }
         self.trapframe = null_mut();
         if !self.pagetable.is_null() {
-            proc_freepagetable(self.pagetable, self.memory_allocated);
+            if self.is_thread() {
+                // The rest of the address space belongs to the group leader.
+                uvmunmap(self.pagetable, self.trapframe_va as usize, 1, false);
+            } else {
+                self.unmap_mappings();
+                proc_freepagetable(self.pagetable, self.memory_allocated);
+            }
         }
         self.pagetable = null_mut();
         self.memory_allocated = 0;
+        self.heap_start = 0;
         self.pid = 0;
         self.parent = null_mut();
         self.chan = null_mut();
         self.killed = 0;
         self.exit_status = 0;
+        self.priority = PRIORITY_DEFAULT;
+        self.vruntime = 0;
+        realtime::release(&self.reservation);
+        self.reservation = Reservation::none();
+        self.affinity = ALL_CPUS;
+        self.user_ticks = 0;
+        self.system_ticks = 0;
+        self.children_user_ticks = 0;
+        self.children_system_ticks = 0;
+        self.limits = ResourceLimit::defaults();
+        self.uid = 0;
+        self.gid = 0;
+        self.trace_mask = 0;
+        self.dumpable = true;
+        self.group_leader = null_mut();
+        self.trapframe_va = TRAPFRAME as u64;
+        self.user_stack = 0;
+        self.pending_signals = 0;
+        self.blocked_signals = 0;
+        self.signal_actions = [SignalAction::new(); NSIG];
+        self.signal_restorer = 0;
+        self.pgid = 0;
+        self.sid = 0;
+        self.stop_signal = 0;
+        self.alarm_deadline = 0;
+        self.alarm_interval = 0;
+        self.traced = false;
+        self.resume_signal = 0;
+        self.step_address = None;
+        self.step_instruction = 0;
         self.state = ProcessState::Unused;
     }
 
     /// Grow or shrink user memory.
+    ///
+    /// Growing only reserves the address range,
+    /// and pages are allocated when they are first used.
+    /// The new size is shared by all threads in the group.
     pub unsafe fn grow_memory(&mut self, num_bytes: i32) -> Result<(), ProcessError> {
+        let leader = addr_of_mut!(*self.leader());
+        let _guard = (*leader).lock.lock();
         let mut size = self.memory_allocated;
 
         if num_bytes > 0 {
-            size = uvmalloc(
-                self.pagetable,
-                size as usize,
-                size.wrapping_add(num_bytes as u64) as usize,
-                PTE_W,
-            );
-
-            if size == 0 {
+            size += num_bytes as u64;
+            // The heap may not reach the mappings.
+            if size + (*leader).mapped_bytes() > self.soft_limit(RLIMIT_AS) || size > MMAP_BASE {
                 return Err(ProcessError::Allocation);
             }
         } else if num_bytes < 0 {
@@ -288,7 +510,32 @@ impl Process {
             );
         }
 
-        self.memory_allocated = size;
+        for p in PROCESSES.iter_mut() {
+            if addr_of_mut!(*p) == leader || p.group_leader == leader {
+                p.memory_allocated = size;
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocate a page for `addr` on first use, if it is in the heap or
+    /// a mapping that allows `access`, made of PROT_* bits.
+    pub unsafe fn page_fault(&mut self, addr: u64, access: i32) -> Result<(), ProcessError> {
+        // The heap can't be executed.
+        if access & PROT_EXEC == 0 && self.heap_fault(addr).is_ok() {
+            return Ok(());
+        }
+        self.mapping_fault(addr, access)
+    }
+
+    /// Allocate a zeroed page for `addr` on first use, if it is in the heap.
+    pub unsafe fn heap_fault(&self, addr: u64) -> Result<(), ProcessError> {
+        if addr < self.heap_start || addr >= self.memory_allocated {
+            return Err(ProcessError::PageError);
+        }
+        if uvmlazy(self.pagetable, addr as usize, PTE_R | PTE_W) != 0 {
+            return Err(ProcessError::Allocation);
+        }
         Ok(())
     }
 
@@ -344,20 +591,27 @@ impl Process {
     /// Sets up child kernel stack to return as if from fork() syscall.
     pub unsafe fn fork() -> Result<i32, ProcessError> {
         let parent = Process::current().unwrap();
+        if !parent.can_fork() {
+            return Err(ProcessError::MaxProcesses);
+        }
         let child = Process::alloc()?;
 
         // Copy user memory from parent to child.
         if uvmcopy(
             parent.pagetable,
             child.pagetable,
+            0,
             parent.memory_allocated as usize,
+            true,
         ) < 0
+            || child.copy_mappings(parent).is_err()
         {
             child.free();
             child.lock.unlock();
             return Err(ProcessError::Allocation);
         }
         child.memory_allocated = parent.memory_allocated;
+        child.heap_start = parent.heap_start;
 
         // Copy saved user registers.
         *child.trapframe = *parent.trapframe;
@@ -366,12 +620,29 @@ impl Process {
         (*child.trapframe).a0 = 0;
 
         // Increment reference counts on open file descriptors.
-        for (i, file) in parent.open_files.iter().enumerate() {
+        let leader = parent.leader();
+        for (i, file) in leader.open_files.iter().enumerate() {
             if !file.is_null() {
-                child.open_files[i] = filedup(parent.open_files[i]);
+                child.open_files[i] = filedup(*file);
             }
         }
-        child.current_dir = idup(parent.current_dir);
+        child.current_dir = idup(leader.current_dir);
+        child.limits = leader.limits;
+        child.uid = leader.uid;
+        child.gid = leader.gid;
+        child.dumpable = leader.dumpable;
+        child.priority = parent.priority;
+        child.affinity = parent.affinity;
+        child.trace_mask = parent.trace_mask;
+        child.vruntime = parent.vruntime;
+        child.blocked_signals = parent.blocked_signals;
+        // The copy of memory has any single-step breakpoint in it too.
+        child.step_address = parent.step_address;
+        child.step_instruction = parent.step_instruction;
+        child.signal_actions = parent.signal_actions;
+        child.signal_restorer = parent.signal_restorer;
+        child.pgid = parent.pgid;
+        child.sid = parent.sid;
 
         let pid = child.pid;
 
@@ -382,12 +653,84 @@ impl Process {
         }
         {
             let _guard = child.lock.lock();
-            child.state = ProcessState::Runnable;
+            child.cpu = Cpu::current_id();
+            make_runnable(addr_of!(*child).cast_mut());
         }
 
         Ok(pid)
     }
 
+    /// Create a new thread sharing the current process's address space,
+    /// open files and current directory. The thread starts in `entry`
+    /// with `arg` as its argument, on the page of user stack at `stack`.
+    pub unsafe fn clone(entry: u64, arg: u64, stack: u64) -> Result<i32, ProcessError> {
+        let parent = Process::current().unwrap();
+
+        if parent.memory_allocated < PAGE_SIZE as u64
+            || stack > parent.memory_allocated - PAGE_SIZE as u64
+        {
+            return Err(ProcessError::InvalidArgument);
+        }
+
+        let thread = Process::alloc()?;
+
+        // Share the parent's page table instead of the one alloc() made,
+        // with this thread's trapframe mapped at its own address.
+        Process::free_pagetable(thread.pagetable, 0);
+        thread.pagetable = parent.pagetable;
+        thread.trapframe_va = thread_trapframe(thread.index) as u64;
+        thread.group_leader = addr_of_mut!(*parent.leader());
+        if mappages(
+            thread.pagetable,
+            thread.trapframe_va as usize,
+            PAGE_SIZE,
+            thread.trapframe as usize,
+            PTE_R | PTE_W,
+        ) < 0
+        {
+            thread.pagetable = null_mut();
+            thread.free();
+            thread.lock.unlock();
+            return Err(ProcessError::Allocation);
+        }
+        thread.memory_allocated = parent.memory_allocated;
+        thread.heap_start = parent.heap_start;
+        thread.user_stack = stack;
+
+        // Start in entry(arg) on the new stack. There's nowhere to
+        // return to, so the thread must call exit() when it's done.
+        *thread.trapframe = *parent.trapframe;
+        (*thread.trapframe).epc = entry;
+        (*thread.trapframe).a0 = arg;
+        (*thread.trapframe).sp = stack + PAGE_SIZE as u64;
+        (*thread.trapframe).ra = u64::MAX;
+
+        thread.priority = parent.priority;
+        thread.affinity = parent.affinity;
+        thread.trace_mask = parent.trace_mask;
+        thread.vruntime = parent.vruntime;
+        thread.blocked_signals = parent.blocked_signals;
+        thread.signal_actions = parent.signal_actions;
+        thread.signal_restorer = parent.signal_restorer;
+        thread.pgid = parent.pgid;
+        thread.sid = parent.sid;
+
+        let tid = thread.pid;
+
+        thread.lock.unlock();
+        {
+            let _guard = WAIT_LOCK.lock();
+            thread.parent = addr_of!(*parent).cast_mut();
+        }
+        {
+            let _guard = thread.lock.lock();
+            thread.cpu = Cpu::current_id();
+            make_runnable(addr_of!(*thread).cast_mut());
+        }
+
+        Ok(tid)
+    }
+
     /// A fork child's very first scheduling by
     /// scheduler() will swtch to forkret.
     pub unsafe fn forkret() -> ! {
@@ -405,17 +748,60 @@ impl Process {
         usertrapret()
     }
 
-    /// Pass p's abandoned children to init.
+    /// Pass p's abandoned children to init,
+    /// and its abandoned threads to their group leader.
     /// Caller must hold WAIT_LOCK.
     pub unsafe fn reparent(&self) {
         for p in PROCESSES.iter_mut() {
             if p.parent == addr_of!(*self).cast_mut() {
-                p.parent = INITPROC as *mut Process;
-                wakeup((INITPROC as *mut Process).cast());
+                p.parent = if p.is_thread() {
+                    p.group_leader
+                } else {
+                    INITPROC as *mut Process
+                };
+                wakeup(p.parent.cast());
+
+                if p.traced {
+                    // A tracee outlives its tracer, carrying on untraced.
+                    p.lock.lock_unguarded();
+                    p.untrace();
+                    p.lock.unlock();
+                }
             }
         }
     }
 
+    /// Kill the other threads in this leader's group, and
+    /// wait until they have all exited and been freed.
+    unsafe fn exit_threads(&mut self) {
+        let guard = WAIT_LOCK.lock();
+
+        loop {
+            let mut has_threads = false;
+
+            for p in PROCESSES.iter_mut() {
+                if p.group_leader == addr_of_mut!(*self) {
+                    has_threads = true;
+
+                    p.lock.lock_unguarded();
+                    if p.state == ProcessState::Zombie {
+                        p.free();
+                    } else {
+                        p.send_signal(SIGKILL);
+                    }
+                    p.lock.unlock();
+                }
+            }
+
+            if !has_threads {
+                return;
+            }
+
+            // Exiting threads wake their leader.
+            guard.sleep(addr_of_mut!(*self).cast());
+        }
+    }
+
     /// Exit the current process. Does not return.
     /// An exited process remains in the zombie state
     /// until its parent calls wait().
@@ -424,19 +810,37 @@ impl Process {
             panic!("init exiting");
         }
 
-        // Close all open files.
-        for file in self.open_files.iter_mut() {
-            if !file.is_null() {
-                fileclose(*file);
-                *file = null_mut();
-            }
+        // Write a core file while memory and the current directory are intact.
+        let signal = {
+            let _guard = self.lock.lock();
+            self.killed as usize
+        };
+        if dumps_core(signal) && self.dump_core(signal).is_ok() {
+            println!("{}: core dumped", self.pid);
         }
 
-        {
-            let _operation = LogOperation::new();
-            iput(self.current_dir);
+        // The open files and current directory belong to the group leader,
+        // which must outlive the threads sharing them.
+        if !self.is_thread() {
+            self.exit_threads();
+
+            // Write back shared file mappings while the files are open.
+            self.unmap_mappings();
+
+            // Close all open files.
+            for file in self.open_files.iter_mut() {
+                if !file.is_null() {
+                    fileclose(*file);
+                    *file = null_mut();
+                }
+            }
+
+            {
+                let _operation = LogOperation::new();
+                iput(self.current_dir);
+            }
+            self.current_dir = null_mut();
         }
-        self.current_dir = null_mut();
 
         {
             let _guard = WAIT_LOCK.lock();
@@ -444,9 +848,22 @@ impl Process {
             // Give any children to init.
             self.reparent();
 
-            // Parent might be sleeping in wait().
+            // Parent might be sleeping in wait() or join().
             wakeup(self.parent.cast());
 
+            // Group leader might be waiting for its threads to exit.
+            if self.is_thread() && self.group_leader != self.parent {
+                wakeup(self.group_leader.cast());
+            }
+
+            // Tell the parent that it has a child to wait for.
+            if !self.is_thread() {
+                let parent = &mut *self.parent;
+                parent.lock.lock_unguarded();
+                parent.send_signal(SIGCHLD);
+                parent.lock.unlock();
+            }
+
             self.lock.lock_unguarded();
             self.exit_status = status;
             self.state = ProcessState::Zombie;
@@ -459,14 +876,63 @@ impl Process {
 
     /// Wait for a child process to exit, and return its pid.
     pub unsafe fn wait_for_child(&mut self, addr: u64) -> Result<i32, ProcessError> {
+        self.wait_for(-1, addr, 0, |p| p.exit_status)
+    }
+
+    /// Wait for the child with the given pid, or any child if pid is -1,
+    /// to exit, and return its pid. The child's wait status is copied
+    /// out to `addr`. With WNOHANG, returns 0 instead of waiting.
+    /// With WUNTRACED, also returns children that have stopped.
+    pub unsafe fn waitpid(
+        &mut self,
+        pid: i32,
+        addr: u64,
+        options: i32,
+    ) -> Result<i32, ProcessError> {
+        if pid == 0 || pid < -1 || options & !(WNOHANG | WUNTRACED) != 0 {
+            return Err(ProcessError::InvalidArgument);
+        }
+        self.wait_for(pid, addr, options, Process::wait_status)
+    }
+
+    /// How a zombie process ended, as reported by waitpid():
+    /// the signal that killed it, or its exit status shifted
+    /// past the low byte. A stopped process reports 0x7f
+    /// and the signal that stopped it.
+    pub fn wait_status(&self) -> i32 {
+        if self.state == ProcessState::Stopped {
+            0x7f | (self.stop_signal << 8)
+        } else if self.killed > 0 {
+            self.killed & 0x7f
+        } else {
+            (self.exit_status & 0xff) << 8
+        }
+    }
+
+    /// Wait for a matching child to exit, copy `status(child)` out to
+    /// `addr`, free the child and return its pid. See waitpid() for `options`.
+    unsafe fn wait_for(
+        &mut self,
+        pid: i32,
+        addr: u64,
+        options: i32,
+        status: fn(&Process) -> i32,
+    ) -> Result<i32, ProcessError> {
         let guard = WAIT_LOCK.lock();
 
         loop {
+            // A stop signal interrupts waiting, as it would in user space.
+            WAIT_LOCK.unlock();
+            self.stop_if_pending();
+            WAIT_LOCK.lock_unguarded();
+
             // Scan through the table looking for exited children.
             let mut has_children = false;
 
             for p in PROCESSES.iter_mut() {
-                if p.parent == addr_of_mut!(*self) {
+                // Threads are collected by join() instead.
+                if p.parent == addr_of_mut!(*self) && !p.is_thread() && (pid == -1 || p.pid == pid)
+                {
                     has_children = true;
 
                     // Ensure the child isn't still in exit() or swtch().
@@ -475,12 +941,16 @@ impl Process {
                     if p.state == ProcessState::Zombie {
                         // Found an exited child.
                         let pid = p.pid;
+                        let mut status = status(p);
+
+                        self.children_user_ticks += p.user_ticks + p.children_user_ticks;
+                        self.children_system_ticks += p.system_ticks + p.children_system_ticks;
 
                         if addr != 0
                             && copyout(
                                 self.pagetable,
                                 addr as usize,
-                                addr_of_mut!(p.exit_status).cast(),
+                                addr_of_mut!(status).cast(),
                                 core::mem::size_of::<i32>(),
                             ) < 0
                         {
@@ -491,6 +961,30 @@ impl Process {
                         p.free();
                         p.lock.unlock();
                         return Ok(pid);
+                    } else if p.state == ProcessState::Stopped
+                        && p.stop_signal != 0
+                        && (options & WUNTRACED != 0 || p.traced)
+                    {
+                        // Found a newly stopped child.
+                        let pid = p.pid;
+                        let mut status = status(p);
+
+                        if addr != 0
+                            && copyout(
+                                self.pagetable,
+                                addr as usize,
+                                addr_of_mut!(status).cast(),
+                                core::mem::size_of::<i32>(),
+                            ) < 0
+                        {
+                            p.lock.unlock();
+                            return Err(ProcessError::PageError);
+                        }
+
+                        // Only report each stop once.
+                        p.stop_signal = 0;
+                        p.lock.unlock();
+                        return Ok(pid);
                     }
 
                     p.lock.unlock();
@@ -499,7 +993,9 @@ impl Process {
 
             if !has_children {
                 return Err(ProcessError::NoChildren);
-            } else if self.is_killed() {
+            } else if options & WNOHANG != 0 {
+                return Ok(0);
+            } else if self.is_interrupted() {
                 return Err(ProcessError::Killed);
             }
 
@@ -509,39 +1005,276 @@ impl Process {
         }
     }
 
-    /// Kill the process with the given pid.
-    /// Returns true if the process was killed.
-    /// The victim won't exit until it tries to return
-    /// to user space (see usertrap() in trap.c).
-    pub unsafe fn kill(pid: i32) -> bool {
-        for p in PROCESSES.iter_mut() {
-            let _guard = p.lock.lock();
+    /// Wait for a thread created by this one to exit, and return its pid.
+    /// The thread's user stack is copied out to `addr` so it can be freed.
+    pub unsafe fn join(&mut self, addr: u64) -> Result<i32, ProcessError> {
+        let guard = WAIT_LOCK.lock();
+
+        loop {
+            // Scan through the table looking for exited threads.
+            let mut has_threads = false;
+
+            for p in PROCESSES.iter_mut() {
+                if p.parent == addr_of_mut!(*self) && p.is_thread() {
+                    has_threads = true;
+
+                    // Ensure the thread isn't still in exit() or swtch().
+                    p.lock.lock_unguarded();
+
+                    if p.state == ProcessState::Zombie {
+                        // Found an exited thread.
+                        let pid = p.pid;
+
+                        self.children_user_ticks += p.user_ticks + p.children_user_ticks;
+                        self.children_system_ticks += p.system_ticks + p.children_system_ticks;
+
+                        if addr != 0
+                            && copyout(
+                                self.pagetable,
+                                addr as usize,
+                                addr_of_mut!(p.user_stack).cast(),
+                                core::mem::size_of::<u64>(),
+                            ) < 0
+                        {
+                            p.lock.unlock();
+                            return Err(ProcessError::PageError);
+                        }
+
+                        p.free();
+                        p.lock.unlock();
+                        return Ok(pid);
+                    }
+
+                    p.lock.unlock();
+                }
+            }
+
+            if !has_threads {
+                return Err(ProcessError::NoChildren);
+            } else if self.is_interrupted() {
+                return Err(ProcessError::Killed);
+            }
+
+            // Wait for a thread to exit.
+            guard.sleep(addr_of_mut!(*self).cast());
+        }
+    }
+
+    /// Find the process with the given pid and
+    /// call `f` on it while holding its lock.
+    pub unsafe fn with_pid<T>(pid: i32, f: impl FnOnce(&mut Process) -> T) -> Option<T> {
+        let p = PROCESSES.find(pid)?;
+        p.lock.lock_unguarded();
+
+        // It may have been freed since it was found.
+        let result = (p.pid == pid && p.state != ProcessState::Unused).then(|| f(p));
+
+        p.lock.unlock();
+        result
+    }
+    /// Set the scheduling priority of the process with the given pid,
+    /// which must belong to the caller's user unless that is root.
+    /// As with nice, only root can raise a priority.
+    pub unsafe fn set_priority(pid: i32, priority: i32) -> Result<(), ProcessError> {
+        if !(PRIORITY_HIGHEST..=PRIORITY_LOWEST).contains(&priority) {
+            return Err(ProcessError::InvalidArgument);
+        }
+        let current = Process::current().unwrap();
+        let uid = current.user_id();
+        let privileged = current.is_privileged();
+        Process::with_pid(pid, |p| {
+            if !p.controllable_by(uid) || (priority < p.priority && !privileged) {
+                return Err(ProcessError::PermissionDenied);
+            }
+            p.priority = priority;
+            Ok(())
+        })
+        .ok_or(ProcessError::NoSuchProcess)?
+    }
+    /// Get the scheduling priority of the process with the given pid.
+    pub unsafe fn get_priority(pid: i32) -> Result<i32, ProcessError> {
+        Process::with_pid(pid, |p| p.priority).ok_or(ProcessError::NoSuchProcess)
+    }
+    /// Restrict the process with the given pid to the harts in `affinity`,
+    /// which must include at least one hart that is running. Only root
+    /// can restrict another user's process.
+    pub unsafe fn set_affinity(pid: i32, affinity: u64) -> Result<(), ProcessError> {
+        if affinity & !ALL_CPUS != 0
+            || !(0..NCPU).any(|cpu| cpu_allowed(affinity, cpu) && RUN_QUEUES[cpu].online)
+        {
+            return Err(ProcessError::InvalidArgument);
+        }
+        let uid = Process::current().unwrap().user_id();
+        let now = uptime();
+
+        Process::with_pid(pid, |p| {
+            if !p.controllable_by(uid) {
+                return Err(ProcessError::PermissionDenied);
+            }
 
-            if p.pid == pid {
-                p.killed = 1;
+            // Requeue a runnable process where it's now allowed to run.
+            let queued = p.state == ProcessState::Runnable && remove_runnable(p);
 
-                if p.state == ProcessState::Sleeping {
-                    // Wake process from sleep().
-                    p.state = ProcessState::Runnable;
+            // A real-time reservation has to be admitted onto an allowed hart.
+            let mut result = Ok(());
+            if p.reservation.is_realtime() && !cpu_allowed(affinity, p.reservation.cpu) {
+                let mut reservation = p.reservation;
+                result = realtime::admit(&p.reservation, &mut reservation, now, affinity);
+                if result.is_ok() {
+                    p.reservation = reservation;
                 }
+            }
+            if result.is_ok() {
+                p.affinity = affinity;
+            }
 
-                return true;
+            if queued {
+                make_runnable(p);
+            } else if p.state == ProcessState::Running && !cpu_allowed(p.affinity, p.cpu) {
+                // Move at the next timer interrupt.
+                p.time_slice = 0;
             }
+            result
+        })
+        .ok_or(ProcessError::NoSuchProcess)?
+    }
+    /// Get the harts the process with the given pid may run on.
+    pub unsafe fn get_affinity(pid: i32) -> Result<u64, ProcessError> {
+        Process::with_pid(pid, |p| p.affinity).ok_or(ProcessError::NoSuchProcess)
+    }
+    /// Whether any process is in the given process group and session.
+    pub unsafe fn group_exists(pgid: i32, sid: i32) -> bool {
+        PROCESSES.iter_mut().any(|p| {
+            let _guard = p.lock.lock();
+            p.state != ProcessState::Unused && p.pgid == pgid && p.sid == sid
+        })
+    }
+    /// Move the process with the given pid into process group `pgid`,
+    /// where 0 means the current process and a group of its own.
+    ///
+    /// Only the current process and its children can be moved,
+    /// and only to a group in the same session.
+    pub unsafe fn set_pgid(pid: i32, pgid: i32) -> Result<(), ProcessError> {
+        let current = Process::current().unwrap();
+        let pid = if pid == 0 { current.pid } else { pid };
+        let pgid = if pgid == 0 { pid } else { pgid };
+
+        if pid < 0 || pgid < 0 {
+            return Err(ProcessError::InvalidArgument);
         }
 
-        false
+        let sid = {
+            let _guard = current.lock.lock();
+            current.sid
+        };
+        // A new group is named after its first process.
+        if pgid != pid && !Process::group_exists(pgid, sid) {
+            return Err(ProcessError::PermissionDenied);
+        }
+
+        let _guard = WAIT_LOCK.lock();
+        let Some(p) = PROCESSES.iter_mut().find(|p| {
+            p.pid == pid
+                && p.state != ProcessState::Unused
+                && (p.is_current() || p.parent == addr_of_mut!(*current))
+        }) else {
+            return Err(ProcessError::NoSuchProcess);
+        };
+
+        let _guard = p.lock.lock();
+        if p.sid != sid || p.pid == p.sid {
+            // Session leaders stay in their own group.
+            return Err(ProcessError::PermissionDenied);
+        }
+        p.pgid = pgid;
+        Ok(())
+    }
+    /// Get the process group of the process with the given pid,
+    /// where 0 means the current process.
+    pub unsafe fn get_pgid(pid: i32) -> Result<i32, ProcessError> {
+        let pid = if pid == 0 {
+            Process::current().unwrap().pid
+        } else {
+            pid
+        };
+        Process::with_pid(pid, |p| p.pgid).ok_or(ProcessError::NoSuchProcess)
+    }
+    /// Start a new session, with the current process as
+    /// the leader of it and of a new process group.
+    pub fn set_sid(&mut self) -> Result<i32, ProcessError> {
+        let _guard = self.lock.lock();
+        if self.pgid == self.pid {
+            return Err(ProcessError::PermissionDenied);
+        }
+        self.sid = self.pid;
+        self.pgid = self.pid;
+        Ok(self.sid)
+    }
+    /// Reserve `runtime` ticks every `period` ticks, to be used within `deadline`
+    /// ticks of the start of each period, for the process with the given pid.
+    ///
+    /// A runtime of zero returns the process to normal scheduling.
+    /// Only root can reserve CPU time, or change another user's process.
+    pub unsafe fn set_reservation(
+        pid: i32,
+        runtime: usize,
+        deadline: usize,
+        period: usize,
+    ) -> Result<(), ProcessError> {
+        let mut reservation = if runtime == 0 {
+            Reservation::none()
+        } else {
+            Reservation::new(runtime, deadline, period)?
+        };
+        let current = Process::current().unwrap();
+        if reservation.is_realtime() && !current.is_privileged() {
+            return Err(ProcessError::PermissionDenied);
+        }
+        let uid = current.user_id();
+        let now = uptime();
+
+        Process::with_pid(pid, |p| {
+            if !p.controllable_by(uid) {
+                return Err(ProcessError::PermissionDenied);
+            }
+            // The run queues read a queued process's reservation under only
+            // their own lock, and file it by its hart, so take it off its
+            // queue while the reservation changes.
+            let queued = p.state == ProcessState::Runnable && remove_runnable(p);
+            let admitted = if reservation.is_realtime() {
+                realtime::admit(&p.reservation, &mut reservation, now, p.affinity)
+            } else {
+                realtime::release(&p.reservation);
+                Ok(())
+            };
+            if admitted.is_ok() {
+                p.reservation = reservation;
+            }
+            if queued {
+                make_runnable(p);
+            }
+            admitted
+        })
+        .ok_or(ProcessError::NoSuchProcess)?
+    }
+    /// CPU time used by this process and its waited-for children.
+    pub fn times(&self) -> Times {
+        Times {
+            user_ticks: self.user_ticks,
+            system_ticks: self.system_ticks,
+            children_user_ticks: self.children_user_ticks,
+            children_system_ticks: self.children_system_ticks,
+        }
     }
     pub fn is_killed(&self) -> bool {
         let _guard = self.lock.lock();
         self.killed > 0
     }
-    pub fn set_killed(&mut self, killed: bool) {
+    /// Mark the process as killed by `signal`,
+    /// whatever it would otherwise do with it.
+    pub fn set_killed(&mut self, signal: usize) {
         let _guard = self.lock.lock();
-        if killed {
-            self.killed = 1;
-        } else {
-            self.killed = 0;
-        }
+        self.killed = signal as i32;
     }
 }
 
@@ -555,6 +1288,17 @@ pub extern "C" fn myproc() -> *mut Process {
     }
 }
 
+/// Return the process holding the current thread's
+/// open files and current directory.
+#[no_mangle]
+pub extern "C" fn mygroupleader() -> *mut Process {
+    if let Some(p) = Process::current() {
+        p.leader() as *mut Process
+    } else {
+        null_mut()
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn proc_pagetable(p: *mut Process) -> Pagetable {
     (*p).alloc_pagetable().unwrap_or(null_mut())
@@ -572,7 +1316,15 @@ pub unsafe fn procdump() {
     uprintln!("\nprocdump:");
     for p in PROCESSES.iter() {
         if p.state != ProcessState::Unused {
-            uprintln!("    {}: {:?}", p.pid, p.state);
+            uprintln!(
+                "    {}: {:?} (group {}, priority {}, {} user ticks, {} system ticks)",
+                p.pid,
+                p.state,
+                p.pgid,
+                p.priority,
+                p.user_ticks,
+                p.system_ticks
+            );
         }
     }
 }
@@ -0,0 +1,455 @@
+//! Process tracing for debuggers, as with ptrace().
+//!
+//! A parent traces one of its children, either by attaching to it or by
+//! the child asking to be traced. A traced process stops instead of
+//! taking each signal sent to it, and the parent sees the stop through
+//! waitpid() and picks the signal, if any, for it to take once continued.
+//! While the child is stopped, the parent can read and write its
+//! registers and memory. Pages of its program that haven't been used
+//! yet are read in for the parent first, and a traced program is read
+//! in whole by exec(), so that single steps can put breakpoints in it.
+//!
+//! Breakpoints are ebreak instructions written into the tracee's code,
+//! which stop it with SIGTRAP. RISC-V has no single-step mode for user
+//! space, so a single step works out which instruction runs next and
+//! puts a breakpoint there, taking it back out once it is hit.
+
+use super::{
+    mmap::PROT_READ,
+    process::{Process, ProcessError, ProcessState, WAIT_LOCK},
+    scheduler::make_runnable,
+    signal::{NSIG, SIGKILL, SIGSTOP, SIGTRAP},
+    trapframe::Trapframe,
+};
+use crate::hal::arch::virtual_memory::{copyin, copyout, walkaddr};
+use core::{mem::size_of, ptr::addr_of_mut};
+
+/// Be traced by the parent.
+pub const PTRACE_TRACEME: i32 = 0;
+/// Read a word of the tracee's memory at `addr` into `*data`.
+pub const PTRACE_PEEKDATA: i32 = 2;
+/// Write the word `data` to the tracee's memory at `addr`.
+pub const PTRACE_POKEDATA: i32 = 5;
+/// Continue the tracee, which takes signal `data` if it isn't 0.
+pub const PTRACE_CONT: i32 = 7;
+/// Kill the tracee.
+pub const PTRACE_KILL: i32 = 8;
+/// Continue the tracee for one instruction, as for PTRACE_CONT.
+pub const PTRACE_SINGLESTEP: i32 = 9;
+/// Copy the tracee's Trapframe to `*data`.
+pub const PTRACE_GETREGS: i32 = 12;
+/// Set the tracee's user registers from the Trapframe at `*data`.
+pub const PTRACE_SETREGS: i32 = 13;
+/// Start tracing a child, stopping it with SIGSTOP.
+pub const PTRACE_ATTACH: i32 = 16;
+/// Stop tracing the tracee and continue it, as for PTRACE_CONT.
+pub const PTRACE_DETACH: i32 = 17;
+
+/// c.ebreak, which is short enough to replace any instruction.
+const C_EBREAK: u16 = 0x9002;
+
+impl Process {
+    /// Carry out a ptrace() request, with `pid` the tracee.
+    pub unsafe fn ptrace(
+        &mut self,
+        request: i32,
+        pid: i32,
+        addr: u64,
+        data: u64,
+    ) -> Result<(), ProcessError> {
+        let pagetable = self.pagetable;
+
+        match request {
+            PTRACE_TRACEME => self.trace_me(),
+            PTRACE_ATTACH => self.attach(pid),
+            PTRACE_PEEKDATA => {
+                self.fault_in_tracee(pid, addr)?;
+                let mut word = self.with_tracee(pid, |p| {
+                    let mut word = 0u64;
+                    if copyin(
+                        p.pagetable,
+                        addr_of_mut!(word).cast(),
+                        addr as usize,
+                        size_of::<u64>(),
+                    ) < 0
+                    {
+                        return Err(ProcessError::PageError);
+                    }
+                    Ok(word)
+                })?;
+                if copyout(
+                    pagetable,
+                    data as usize,
+                    addr_of_mut!(word).cast(),
+                    size_of::<u64>(),
+                ) < 0
+                {
+                    return Err(ProcessError::PageError);
+                }
+                Ok(())
+            }
+            PTRACE_POKEDATA => {
+                self.fault_in_tracee(pid, addr)?;
+                self.with_tracee(pid, |p| {
+                    let mut word = data;
+                    if copyout(
+                        p.pagetable,
+                        addr as usize,
+                        addr_of_mut!(word).cast(),
+                        size_of::<u64>(),
+                    ) < 0
+                    {
+                        return Err(ProcessError::PageError);
+                    }
+                    Ok(())
+                })
+            }
+            PTRACE_GETREGS => {
+                let mut registers = self.with_tracee(pid, |p| Ok(*p.trapframe))?;
+                if copyout(
+                    pagetable,
+                    data as usize,
+                    addr_of_mut!(registers).cast(),
+                    size_of::<Trapframe>(),
+                ) < 0
+                {
+                    return Err(ProcessError::PageError);
+                }
+                Ok(())
+            }
+            PTRACE_SETREGS => {
+                let mut registers = Trapframe::new();
+                if copyin(
+                    pagetable,
+                    addr_of_mut!(registers).cast(),
+                    data as usize,
+                    size_of::<Trapframe>(),
+                ) < 0
+                {
+                    return Err(ProcessError::PageError);
+                }
+                self.with_tracee(pid, |p| {
+                    (*p.trapframe).set_user_registers(&registers);
+                    Ok(())
+                })
+            }
+            PTRACE_CONT | PTRACE_SINGLESTEP | PTRACE_DETACH => {
+                let signal = data as usize;
+                if signal >= NSIG {
+                    return Err(ProcessError::InvalidArgument);
+                }
+                if request == PTRACE_SINGLESTEP {
+                    // The instruction and where the breakpoint goes
+                    // can't be read in with the lock held.
+                    let pc = self.with_tracee(pid, |p| Ok((*p.trapframe).epc))?;
+                    self.fault_in_tracee(pid, pc)?;
+                    let next = self.with_tracee(pid, |p| p.step_target())?;
+                    self.fault_in_tracee(pid, next)?;
+                }
+                self.with_tracee(pid, |p| {
+                    if request == PTRACE_SINGLESTEP {
+                        p.set_step_breakpoint()?;
+                    }
+                    p.resume_signal = signal;
+                    if request == PTRACE_DETACH {
+                        p.untrace();
+                    } else {
+                        p.stop_signal = 0;
+                        make_runnable(addr_of_mut!(*p));
+                    }
+                    Ok(())
+                })
+            }
+            PTRACE_KILL => self.with_tracee(pid, |p| {
+                p.send_signal(SIGKILL);
+                Ok(())
+            }),
+            _ => Err(ProcessError::InvalidArgument),
+        }
+    }
+
+    /// Whether the parent is tracing this process.
+    pub fn is_traced(&self) -> bool {
+        let _guard = self.lock.lock();
+        self.traced
+    }
+    /// Take the signal the tracer picked when it continued the process.
+    pub fn take_resume_signal(&mut self) -> Option<usize> {
+        let _guard = self.lock.lock();
+        match core::mem::take(&mut self.resume_signal) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    /// Let the parent trace this process.
+    unsafe fn trace_me(&mut self) -> Result<(), ProcessError> {
+        let _guard = WAIT_LOCK.lock();
+        if self.parent.is_null() || self.is_thread() {
+            return Err(ProcessError::InvalidArgument);
+        }
+
+        let _guard = self.lock.lock();
+        if self.traced {
+            return Err(ProcessError::PermissionDenied);
+        }
+        self.traced = true;
+        Ok(())
+    }
+
+    /// Start tracing the child with the given pid, and stop it.
+    unsafe fn attach(&mut self, pid: i32) -> Result<(), ProcessError> {
+        let tracer = addr_of_mut!(*self);
+        let _guard = WAIT_LOCK.lock();
+
+        Process::with_pid(pid, |p| {
+            if p.parent != tracer || p.is_thread() || p.traced {
+                return Err(ProcessError::PermissionDenied);
+            }
+            p.traced = true;
+            p.send_signal(SIGSTOP);
+            Ok(())
+        })
+        .unwrap_or(Err(ProcessError::NoSuchProcess))
+    }
+
+    /// Run `f` on the child with the given pid, which must be
+    /// stopped and traced by this process. Its p.lock is held.
+    unsafe fn with_tracee<T>(
+        &mut self,
+        pid: i32,
+        f: impl FnOnce(&mut Process) -> Result<T, ProcessError>,
+    ) -> Result<T, ProcessError> {
+        let tracer = addr_of_mut!(*self);
+        let _guard = WAIT_LOCK.lock();
+
+        Process::with_pid(pid, |p| {
+            if p.parent != tracer || !p.traced || p.state != ProcessState::Stopped {
+                Err(ProcessError::NoSuchProcess)
+            } else {
+                f(p)
+            }
+        })
+        .unwrap_or(Err(ProcessError::NoSuchProcess))
+    }
+
+    /// Read in the pages of the word at `addr` in the child with the given
+    /// pid, if they haven't been used yet. That can sleep, so it can't be
+    /// done under the lock with_tracee() holds.
+    unsafe fn fault_in_tracee(&mut self, pid: i32, addr: u64) -> Result<(), ProcessError> {
+        // The child stays stopped, and its memory isn't freed
+        // until this process, its parent, waits for it.
+        let tracee = self.with_tracee(pid, |p| Ok(addr_of_mut!(*p)))?;
+        for va in [addr, addr.saturating_add(size_of::<u64>() as u64 - 1)] {
+            if walkaddr((*tracee).pagetable, va as usize) == 0 {
+                // A bad address fails when it is copied.
+                let _ = (*tracee).page_fault(va, PROT_READ);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop tracing the process, continuing it if it is stopped.
+    /// Any single-step breakpoint comes out when it is hit.
+    /// p.lock must be held.
+    pub unsafe fn untrace(&mut self) {
+        self.traced = false;
+        if self.state == ProcessState::Stopped {
+            self.stop_signal = 0;
+            make_runnable(addr_of_mut!(*self));
+        }
+    }
+
+    /// Put a breakpoint on the instruction that runs after the one the
+    /// process is stopped at. p.lock must be held.
+    unsafe fn set_step_breakpoint(&mut self) -> Result<(), ProcessError> {
+        self.clear_step_breakpoint();
+
+        let next = self.step_target()?;
+        let mut breakpoint = C_EBREAK;
+        if copyin(
+            self.pagetable,
+            addr_of_mut!(self.step_instruction).cast(),
+            next as usize,
+            2,
+        ) < 0
+            || copyout(
+                self.pagetable,
+                next as usize,
+                addr_of_mut!(breakpoint).cast(),
+                2,
+            ) < 0
+        {
+            return Err(ProcessError::PageError);
+        }
+        self.step_address = Some(next);
+        Ok(())
+    }
+    /// The address of the instruction that runs after the one
+    /// the process is stopped at. p.lock must be held.
+    unsafe fn step_target(&mut self) -> Result<u64, ProcessError> {
+        // Read the first half of the instruction, then the rest if it isn't compressed.
+        let pc = (*self.trapframe).epc;
+        let mut instruction = 0u32;
+        if copyin(
+            self.pagetable,
+            addr_of_mut!(instruction).cast(),
+            pc as usize,
+            2,
+        ) < 0
+            || (instruction & 0b11 == 0b11
+                && copyin(
+                    self.pagetable,
+                    addr_of_mut!(instruction).cast(),
+                    pc as usize,
+                    4,
+                ) < 0)
+        {
+            return Err(ProcessError::PageError);
+        }
+        Ok(next_instruction(&*self.trapframe, instruction))
+    }
+    /// Put back the instruction a single step's breakpoint replaced.
+    /// p.lock must be held.
+    unsafe fn clear_step_breakpoint(&mut self) {
+        if let Some(address) = self.step_address.take() {
+            copyout(
+                self.pagetable,
+                address as usize,
+                addr_of_mut!(self.step_instruction).cast(),
+                2,
+            );
+        }
+    }
+
+    /// Handle an ebreak from user space, which sends SIGTRAP,
+    /// unless it ended a single step of a process no longer traced.
+    pub unsafe fn breakpoint(&mut self) {
+        let pc = (*self.trapframe).epc;
+        self.lock.lock_unguarded();
+        let stepped = self.step_address == Some(pc);
+        if stepped {
+            self.step_address = None;
+        }
+        let mut instruction = self.step_instruction;
+        self.lock.unlock();
+
+        if stepped {
+            // Run the real instruction when the process continues. Writing
+            // the program's pages can look up its mappings, which takes the
+            // lock, so it isn't held.
+            copyout(
+                self.pagetable,
+                pc as usize,
+                addr_of_mut!(instruction).cast(),
+                2,
+            );
+        }
+
+        self.lock.lock_unguarded();
+        if !stepped || self.traced {
+            self.send_signal(SIGTRAP);
+        }
+        self.lock.unlock();
+    }
+
+    /// Forget any single step, whose breakpoint went with the old program,
+    /// and stop a traced process before the new program starts, with all
+    /// of it read in for the tracer.
+    pub unsafe fn exec_trace(&mut self) {
+        let traced = {
+            let _guard = self.lock.lock();
+            self.step_address = None;
+            self.traced
+        };
+        if !traced {
+            return;
+        }
+
+        self.load_program();
+        self.lock.lock_unguarded();
+        self.send_signal(SIGTRAP);
+        self.lock.unlock();
+    }
+}
+
+/// Where the program goes after `instruction` at `registers.epc`.
+fn next_instruction(registers: &Trapframe, instruction: u32) -> u64 {
+    let pc = registers.epc;
+    let bits = |high: u32, low: u32| ((instruction >> low) & ((1 << (high - low + 1)) - 1)) as u64;
+
+    if instruction & 0b11 != 0b11 {
+        // Compressed instruction.
+        let rs1 = bits(11, 7) as usize;
+        let compressed_rs1 = 8 + bits(9, 7) as usize;
+        return match (bits(1, 0), bits(15, 13)) {
+            // c.j
+            (0b01, 0b101) => {
+                let offset = bits(12, 12) << 11
+                    | bits(11, 11) << 4
+                    | bits(10, 9) << 8
+                    | bits(8, 8) << 10
+                    | bits(7, 7) << 6
+                    | bits(6, 6) << 7
+                    | bits(5, 3) << 1
+                    | bits(2, 2) << 5;
+                pc.wrapping_add(sign_extend(offset, 12))
+            }
+            // c.beqz and c.bnez
+            (0b01, funct3 @ (0b110 | 0b111)) => {
+                let offset = bits(12, 12) << 8
+                    | bits(11, 10) << 3
+                    | bits(6, 5) << 6
+                    | bits(4, 3) << 1
+                    | bits(2, 2) << 5;
+                let zero = registers.register(compressed_rs1) == 0;
+                if zero == (funct3 == 0b110) {
+                    pc.wrapping_add(sign_extend(offset, 9))
+                } else {
+                    pc + 2
+                }
+            }
+            // c.jr and c.jalr
+            (0b10, 0b100) if bits(6, 2) == 0 && rs1 != 0 => registers.register(rs1) & !1,
+            _ => pc + 2,
+        };
+    }
+
+    let rs1 = registers.register(bits(19, 15) as usize);
+    let rs2 = registers.register(bits(24, 20) as usize);
+    match bits(6, 0) {
+        // jal
+        0b1101111 => {
+            let offset =
+                bits(31, 31) << 20 | bits(30, 21) << 1 | bits(20, 20) << 11 | bits(19, 12) << 12;
+            pc.wrapping_add(sign_extend(offset, 21))
+        }
+        // jalr
+        0b1100111 => rs1.wrapping_add(sign_extend(bits(31, 20), 12)) & !1,
+        // Branches
+        0b1100011 => {
+            let taken = match bits(14, 12) {
+                0b000 => rs1 == rs2,
+                0b001 => rs1 != rs2,
+                0b100 => (rs1 as i64) < rs2 as i64,
+                0b101 => rs1 as i64 >= rs2 as i64,
+                0b110 => rs1 < rs2,
+                0b111 => rs1 >= rs2,
+                _ => false,
+            };
+            if taken {
+                let offset =
+                    bits(31, 31) << 12 | bits(30, 25) << 5 | bits(11, 8) << 1 | bits(7, 7) << 11;
+                pc.wrapping_add(sign_extend(offset, 13))
+            } else {
+                pc + 4
+            }
+        }
+        _ => pc + 4,
+    }
+}
+
+/// Sign-extend the low `width` bits of `value`.
+fn sign_extend(value: u64, width: u32) -> u64 {
+    (((value << (64 - width)) as i64) >> (64 - width)) as u64
+}
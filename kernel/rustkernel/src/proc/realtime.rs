@@ -0,0 +1,142 @@
+//! Earliest-deadline-first real-time scheduling class.
+//!
+//! A real-time process holds a reservation of `runtime` ticks of CPU
+//! time every `period` ticks, to be used within `deadline` ticks of the
+//! start of each period. Reservations are admitted onto a single hart
+//! only if the total density, runtime over deadline, of that hart stays
+//! at or below one. That is the utilization when deadlines equal periods,
+//! and keeps every deadline met when they are shorter.
+
+use super::{
+    process::ProcessError,
+    scheduler::{cpu_allowed, RUN_QUEUES},
+};
+use crate::{sync::spinlock::Spinlock, NCPU};
+
+/// Utilization is tracked in fractions of `UTILIZATION_SCALE`.
+pub const UTILIZATION_SCALE: usize = 1 << 16;
+
+/// Serializes changes to the per-hart utilization totals.
+static ADMISSION_LOCK: Spinlock = Spinlock::new();
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Reservation {
+    /// Ticks of CPU time guaranteed each period.
+    pub runtime: usize,
+    /// Ticks after the start of a period by which the runtime is used.
+    pub deadline: usize,
+    /// Ticks between the starts of successive periods.
+    pub period: usize,
+    /// Hart the reservation was admitted onto.
+    pub cpu: usize,
+    /// Runtime left in the current period.
+    pub budget: usize,
+    /// Tick at which the current period started.
+    pub period_start: usize,
+}
+impl Reservation {
+    pub const fn none() -> Reservation {
+        Reservation {
+            runtime: 0,
+            deadline: 0,
+            period: 0,
+            cpu: 0,
+            budget: 0,
+            period_start: 0,
+        }
+    }
+    /// Requires 0 < runtime <= deadline <= period.
+    pub fn new(
+        runtime: usize,
+        deadline: usize,
+        period: usize,
+    ) -> Result<Reservation, ProcessError> {
+        if runtime == 0 || runtime > deadline || deadline > period {
+            return Err(ProcessError::InvalidArgument);
+        }
+
+        Ok(Reservation {
+            runtime,
+            deadline,
+            period,
+            ..Reservation::none()
+        })
+    }
+    pub fn is_realtime(&self) -> bool {
+        self.period > 0
+    }
+    /// Fraction of a hart this reservation needs, out of
+    /// `UTILIZATION_SCALE`: the runtime over the deadline,
+    /// rounded up so that admission never overcommits.
+    pub fn density(&self) -> usize {
+        if self.is_realtime() {
+            (self.runtime * UTILIZATION_SCALE).div_ceil(self.deadline)
+        } else {
+            0
+        }
+    }
+    pub fn absolute_deadline(&self) -> usize {
+        self.period_start + self.deadline
+    }
+    /// Start a new period with a full budget if the current one has ended.
+    pub fn replenish(&mut self, now: usize) {
+        if !self.is_realtime() {
+            return;
+        }
+        if now >= self.period_start + self.period {
+            let periods = (now - self.period_start) / self.period;
+            self.period_start += periods * self.period;
+            self.budget = self.runtime;
+        }
+    }
+    /// Can this reservation run right now?
+    pub fn is_eligible(&self) -> bool {
+        self.budget > 0
+    }
+}
+
+/// Admit `new` onto the first online hart in `affinity` with enough spare
+/// capacity, after releasing `old` (which may be `Reservation::none()`).
+///
+/// On success, `new.cpu` holds the chosen hart and its first period starts at `now`.
+/// On failure, `old` stays admitted.
+pub unsafe fn admit(
+    old: &Reservation,
+    new: &mut Reservation,
+    now: usize,
+    affinity: u64,
+) -> Result<(), ProcessError> {
+    let _guard = ADMISSION_LOCK.lock();
+
+    if old.is_realtime() {
+        RUN_QUEUES[old.cpu].utilization -= old.density();
+    }
+
+    let utilization = new.density();
+    let Some(cpu) = (0..NCPU).find(|cpu| {
+        let run_queue = &RUN_QUEUES[*cpu];
+        run_queue.online
+            && cpu_allowed(affinity, *cpu)
+            && run_queue.utilization + utilization <= UTILIZATION_SCALE
+    }) else {
+        if old.is_realtime() {
+            RUN_QUEUES[old.cpu].utilization += old.density();
+        }
+        return Err(ProcessError::Oversubscribed);
+    };
+
+    RUN_QUEUES[cpu].utilization += utilization;
+    new.cpu = cpu;
+    new.period_start = now;
+    new.budget = new.runtime;
+    Ok(())
+}
+
+/// Give back the capacity held by a reservation.
+pub unsafe fn release(reservation: &Reservation) {
+    if reservation.is_realtime() {
+        let _guard = ADMISSION_LOCK.lock();
+        RUN_QUEUES[reservation.cpu].utilization -= reservation.density();
+    }
+}
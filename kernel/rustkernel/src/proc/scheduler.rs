@@ -1,12 +1,22 @@
 use super::{
     context::Context,
-    cpu::Cpu,
-    process::{Process, ProcessState, PROCESSES},
+    cpu::{Cpu, CPUS},
+    process::{Process, ProcessState, PRIORITY_LOWEST},
+    table::PROCESSES,
 };
+#[cfg(feature = "tickless")]
+use crate::hal::arch::clock::{charge_ticks_locked, program_timer};
 use crate::{
     console::printf::println,
-    hal::arch,
-    sync::spinlock::{Spinlock, SpinlockGuard},
+    hal::arch::{
+        self,
+        clock::{interrupt_hart, send_ipi, uptime},
+    },
+    sync::{
+        mutex::Mutex,
+        spinlock::{Spinlock, SpinlockGuard},
+    },
+    NCPU,
 };
 use core::{
     ffi::c_void,
@@ -19,47 +29,488 @@ extern "C" {
     pub fn swtch(a: *mut Context, b: *mut Context);
 }
 
+/// Number of timer ticks a process at the lowest
+/// priority may run before it is preempted.
+pub const TIME_SLICE: usize = 1;
+
+/// Length of a process's time slice in timer ticks.
+///
+/// Every ten levels of priority above the lowest add another `TIME_SLICE`.
+pub fn time_slice(priority: i32) -> usize {
+    TIME_SLICE * (1 + (PRIORITY_LOWEST - priority) as usize / 10)
+}
+
+/// Virtual runtime charged per timer tick to a process
+/// whose time slice is one `TIME_SLICE`.
+///
+/// Processes with longer time slices are charged proportionally less.
+pub const VRUNTIME_PER_TICK: u64 = 12;
+/// How far behind the least virtual runtime on its run queue
+/// a process may be placed when it wakes up.
+#[cfg(feature = "fair-share")]
+pub const SLEEPER_CREDIT: u64 = VRUNTIME_PER_TICK;
+
+/// Affinity mask that lets a process run on any hart.
+pub const ALL_CPUS: u64 = (1 << NCPU) - 1;
+
+/// Whether an affinity mask lets a process run on the given hart.
+pub fn cpu_allowed(affinity: u64, cpu: usize) -> bool {
+    affinity & (1 << cpu) != 0
+}
+
+/// Virtual runtime charged for one timer tick at the given priority.
+pub fn vruntime_per_tick(priority: i32) -> u64 {
+    VRUNTIME_PER_TICK * TIME_SLICE as u64 / time_slice(priority) as u64
+}
+
+/// Load averages are fixed-point numbers with this many fraction bits.
+pub const LOAD_SHIFT: u32 = 11;
+const LOAD_ONE: u64 = 1 << LOAD_SHIFT;
+/// Timer ticks between samples of the number of runnable processes,
+/// about five seconds.
+pub const LOAD_INTERVAL: usize = 50;
+/// How much of the 1, 5 and 15 minute averages is kept each sample,
+/// out of `LOAD_ONE`: e^(-5s/1min) and so on.
+const LOAD_DECAY: [u64; 3] = [1884, 2014, 2037];
+
+/// Exponentially smoothed number of runnable processes, over 1, 5 and
+/// 15 minutes, in fractions of `1 << LOAD_SHIFT`.
+pub static LOAD_AVERAGES: Mutex<[u64; 3]> = Mutex::new([0; 3]);
+
+/// Fold the number of processes running or waiting
+/// to run into the load averages.
+///
+/// Called by clockintr() every `LOAD_INTERVAL` ticks.
+/// Reads the run queues without their locks, so the count is approximate.
+pub unsafe fn update_load_averages() {
+    let queued: usize = RUN_QUEUES
+        .iter()
+        .map(|run_queue| run_queue.processes.len() + run_queue.realtime.len())
+        .sum();
+    let running = CPUS.iter().filter(|cpu| !cpu.proc.is_null()).count();
+    let runnable = (queued + running) as u64 * LOAD_ONE;
+
+    let mut averages = LOAD_AVERAGES.lock_spinning();
+    for (average, decay) in averages.iter_mut().zip(LOAD_DECAY) {
+        *average = (*average * decay + runnable * (LOAD_ONE - decay)) >> LOAD_SHIFT;
+    }
+}
+
+/// System-wide statistics, as reported by sysinfo().
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SystemInfo {
+    /// Timer ticks since boot
+    pub uptime: u64,
+    /// 1, 5 and 15 minute load averages, in fractions of `1 << LOAD_SHIFT`
+    pub loads: [u64; 3],
+    /// Number of processes
+    pub processes: u64,
+    /// Number of harts running the scheduler
+    pub cpus: u64,
+    /// Timer ticks each hart has spent idle
+    pub idle_ticks: [u64; NCPU],
+    /// Timer interrupts each hart has taken
+    pub timer_interrupts: [u64; NCPU],
+}
+impl SystemInfo {
+    pub unsafe fn current() -> SystemInfo {
+        let mut info = SystemInfo {
+            uptime: uptime() as u64,
+            loads: *LOAD_AVERAGES.lock_spinning(),
+            processes: PROCESSES.in_use() as u64,
+            cpus: RUN_QUEUES
+                .iter()
+                .filter(|run_queue| run_queue.online)
+                .count() as u64,
+            idle_ticks: [0; NCPU],
+            timer_interrupts: [0; NCPU],
+        };
+        for (id, cpu) in CPUS.iter().enumerate() {
+            info.idle_ticks[id] = cpu.idle_ticks;
+            info.timer_interrupts[id] = cpu.timer_interrupts;
+        }
+        info
+    }
+}
+
+/// A first-in first-out list of processes, linked through
+/// `Process::run_next` so that it can hold any number of them.
+/// A process can only be on one list at a time.
+pub struct ProcessQueue {
+    head: *mut Process,
+    tail: *mut Process,
+    len: usize,
+}
+impl ProcessQueue {
+    pub const fn new() -> ProcessQueue {
+        ProcessQueue {
+            head: null_mut(),
+            tail: null_mut(),
+            len: 0,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub unsafe fn push_back(&mut self, p: *mut Process) {
+        (*p).run_next = null_mut();
+        if self.tail.is_null() {
+            self.head = p;
+        } else {
+            (*self.tail).run_next = p;
+        }
+        self.tail = p;
+        self.len += 1;
+    }
+    pub unsafe fn pop_front(&mut self) -> Option<*mut Process> {
+        self.remove(0)
+    }
+    /// Iterates over the processes from front to back without removing them.
+    pub fn iter(&self) -> impl Iterator<Item = *mut Process> {
+        let mut next = self.head;
+        core::iter::from_fn(move || {
+            let p = next;
+            if p.is_null() {
+                return None;
+            }
+            next = unsafe { (*p).run_next };
+            Some(p)
+        })
+    }
+    /// Removes the process `index` places from the front.
+    pub unsafe fn remove(&mut self, index: usize) -> Option<*mut Process> {
+        if index >= self.len {
+            return None;
+        }
+
+        let mut previous: *mut Process = null_mut();
+        let mut p = self.head;
+        for _ in 0..index {
+            previous = p;
+            p = (*p).run_next;
+        }
+
+        if previous.is_null() {
+            self.head = (*p).run_next;
+        } else {
+            (*previous).run_next = (*p).run_next;
+        }
+        if self.tail == p {
+            self.tail = previous;
+        }
+        (*p).run_next = null_mut();
+        self.len -= 1;
+        Some(p)
+    }
+}
+
+/// A per-CPU queue of runnable processes.
+pub struct RunQueue {
+    pub lock: Spinlock,
+    pub processes: ProcessQueue,
+    /// Runnable real-time processes, which run before any in `processes`.
+    pub realtime: ProcessQueue,
+    /// Least virtual runtime of any process started from this queue.
+    pub min_vruntime: u64,
+    /// Total density of the real-time reservations admitted onto this CPU.
+    /// realtime::ADMISSION_LOCK must be held when using this.
+    pub utilization: usize,
+    /// Whether this CPU has started scheduling.
+    pub online: bool,
+}
+impl RunQueue {
+    pub const fn new() -> RunQueue {
+        RunQueue {
+            lock: Spinlock::new(),
+            processes: ProcessQueue::new(),
+            realtime: ProcessQueue::new(),
+            min_vruntime: 0,
+            utilization: 0,
+            online: false,
+        }
+    }
+    pub fn push(&mut self, p: *mut Process) {
+        let _guard = self.lock.lock();
+        let queue = if unsafe { (*p).reservation.is_realtime() } {
+            &mut self.realtime
+        } else {
+            &mut self.processes
+        };
+        unsafe { queue.push_back(p) };
+    }
+    /// Take a runnable process off this queue, returning whether it was
+    /// on it. It isn't once a scheduler has picked it, even before the
+    /// scheduler has taken p.lock to run it.
+    pub fn remove(&mut self, p: *mut Process) -> bool {
+        let _guard = self.lock.lock();
+        for queue in [&mut self.processes, &mut self.realtime] {
+            if let Some(index) = queue.iter().position(|q| q == p) {
+                unsafe { queue.remove(index) };
+                return true;
+            }
+        }
+        false
+    }
+    /// Take the eligible real-time process with the earliest deadline.
+    pub fn pop_realtime(&mut self, now: usize) -> Option<*mut Process> {
+        let _guard = self.lock.lock();
+        let index = self.earliest_deadline(now)?.0;
+        unsafe { self.realtime.remove(index) }
+    }
+    /// Earliest deadline of any eligible real-time process waiting on this queue.
+    pub fn next_deadline(&mut self, now: usize) -> Option<usize> {
+        let _guard = self.lock.lock();
+        Some(self.earliest_deadline(now)?.1)
+    }
+    /// Index and deadline of the eligible real-time process with the earliest deadline,
+    /// replenishing the budgets of any whose period has ended.
+    /// self.lock must be held.
+    fn earliest_deadline(&self, now: usize) -> Option<(usize, usize)> {
+        self.realtime
+            .iter()
+            .enumerate()
+            .filter_map(|(index, p)| {
+                let reservation = unsafe { &mut (*p).reservation };
+                reservation.replenish(now);
+                if reservation.is_eligible() {
+                    Some((index, reservation.absolute_deadline()))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(_, deadline)| *deadline)
+    }
+    /// Tick at which the next real-time process waiting on this
+    /// queue with its budget used up gets a new one.
+    pub fn next_release(&mut self) -> Option<usize> {
+        let _guard = self.lock.lock();
+        self.realtime
+            .iter()
+            .map(|p| unsafe { &(*p).reservation })
+            .filter(|reservation| !reservation.is_eligible())
+            .map(|reservation| reservation.period_start + reservation.period)
+            .min()
+    }
+    /// Take the process that has waited longest, of those allowed to run on `cpu`.
+    #[cfg(not(feature = "fair-share"))]
+    pub fn pop(&mut self, cpu: usize) -> Option<*mut Process> {
+        let _guard = self.lock.lock();
+        let index = self
+            .processes
+            .iter()
+            .position(|p| cpu_allowed(unsafe { (*p).affinity }, cpu))?;
+        unsafe { self.processes.remove(index) }
+    }
+    /// Take the process with the least virtual runtime, of those allowed to run on `cpu`.
+    #[cfg(feature = "fair-share")]
+    pub fn pop(&mut self, cpu: usize) -> Option<*mut Process> {
+        let _guard = self.lock.lock();
+        let (index, _) = self
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| cpu_allowed(unsafe { (**p).affinity }, cpu))
+            .min_by_key(|(_, p)| unsafe { (**p).vruntime })?;
+        let p = unsafe { self.processes.remove(index)? };
+        self.min_vruntime = self.min_vruntime.max(unsafe { (*p).vruntime });
+        Some(p)
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_RUN_QUEUE: RunQueue = RunQueue::new();
+pub static mut RUN_QUEUES: [RunQueue; NCPU] = [EMPTY_RUN_QUEUE; NCPU];
+
+/// The CPU whose run queue a runnable process is on.
+unsafe fn queue_cpu(p: *mut Process) -> usize {
+    if (*p).reservation.is_realtime() {
+        (*p).reservation.cpu
+    } else {
+        (*p).cpu
+    }
+}
+
+/// Whether `p` may be run on `cpu`. p.lock must be held.
+fn may_run_on(p: &Process, cpu: usize) -> bool {
+    if p.reservation.is_realtime() {
+        p.reservation.cpu == cpu
+    } else {
+        cpu_allowed(p.affinity, cpu)
+    }
+}
+
+/// Mark a process as runnable and add it to its CPU's run queue.
+/// Real-time processes always go back to the CPU they were admitted onto.
+/// Other processes move to a CPU their affinity allows if need be.
+/// p.lock must be held.
+pub unsafe fn make_runnable(p: *mut Process) {
+    if !cpu_allowed((*p).affinity, (*p).cpu) {
+        let allowed = |cpu: &usize| cpu_allowed((*p).affinity, *cpu);
+        (*p).cpu = (0..NCPU)
+            .filter(allowed)
+            .find(|cpu| RUN_QUEUES[*cpu].online)
+            .or_else(|| (0..NCPU).find(allowed))
+            .unwrap_or(0);
+    }
+    let run_queue = &mut RUN_QUEUES[queue_cpu(p)];
+
+    // Don't let a process that has been sleeping (or was just created)
+    // keep a virtual runtime far behind everything else on the queue,
+    // or it would monopolize the hart until it caught up.
+    #[cfg(feature = "fair-share")]
+    if (*p).state != ProcessState::Running {
+        let floor = run_queue.min_vruntime.saturating_sub(SLEEPER_CREDIT);
+        (*p).vruntime = (*p).vruntime.max(floor);
+    }
+
+    (*p).state = ProcessState::Runnable;
+    run_queue.push(p);
+
+    // An idle hart waits for an interrupt, so tell it that it has something
+    // to run. Without a tick, a busy one also has to be told that it may
+    // need to preempt. Any other process can be stolen, so if its hart is
+    // busy (say it was just forked there), wake an idle one to take it.
+    let cpu = queue_cpu(p);
+    if CPUS[cpu].proc.is_null() {
+        send_ipi(cpu);
+    } else if (*p).reservation.is_realtime() {
+        if cfg!(feature = "tickless") {
+            interrupt_hart(cpu);
+        }
+    } else if let Some(idle) = (0..NCPU).find(|cpu| {
+        cpu_allowed((*p).affinity, *cpu) && RUN_QUEUES[*cpu].online && CPUS[*cpu].proc.is_null()
+    }) {
+        send_ipi(idle);
+    }
+}
+
+/// Take a runnable process off its run queue, to change where it should
+/// run, returning whether it was there to take. If not, a scheduler has
+/// picked it and will check where it may run. p.lock must be held.
+pub unsafe fn remove_runnable(p: *mut Process) -> bool {
+    RUN_QUEUES[queue_cpu(p)].remove(p)
+}
+
+/// Pick the next process for this CPU to run.
+///
+/// Eligible real-time processes on this CPU run first, in order of deadline.
+/// Otherwise takes from this CPU's run queue, and steals a process
+/// allowed to run here from the busiest other CPU if it is empty.
+unsafe fn next_process(cpu_id: usize) -> Option<*mut Process> {
+    // Read the time before taking any run queue lock, since
+    // clockintr() holds CLOCK_TICKS while it wakes processes up.
+    let now = uptime();
+    if let Some(p) = RUN_QUEUES[cpu_id].pop_realtime(now) {
+        return Some(p);
+    }
+
+    if let Some(p) = RUN_QUEUES[cpu_id].pop(cpu_id) {
+        return Some(p);
+    }
+
+    let busiest = (0..NCPU)
+        .filter(|id| *id != cpu_id)
+        .max_by_key(|id| RUN_QUEUES[*id].processes.len())?;
+    let p = RUN_QUEUES[busiest].pop(cpu_id)?;
+
+    // Virtual runtimes are only comparable within a run queue,
+    // so carry the stolen process's lead or lag over to ours.
+    #[cfg(feature = "fair-share")]
+    {
+        let lag = (*p)
+            .vruntime
+            .saturating_sub(RUN_QUEUES[busiest].min_vruntime);
+        (*p).vruntime = RUN_QUEUES[cpu_id].min_vruntime + lag;
+    }
+
+    Some(p)
+}
+
 /// Give up the CPU for one scheduling round.
 pub unsafe fn r#yield() {
     let p = Process::current().unwrap();
     let _guard = p.lock.lock();
-    p.state = ProcessState::Runnable;
+    make_runnable(addr_of!(*p).cast_mut());
     sched();
 }
 
 // Per-CPU process scheduler.
 // Each CPU calls scheduler() after setting itself up.
 // Scheduler never returns.  It loops, doing:
-//  - choose a process to run.
+//  - take the next process from the run queue.
 //  - swtch to start running that process.
 //  - eventually that process transfers control
 //    via swtch back to the scheduler.
 pub unsafe fn scheduler() -> ! {
-    println!("hart {} starting scheduler", Cpu::current_id());
+    let cpu_id = Cpu::current_id();
+    println!("hart {} starting scheduler", cpu_id);
 
     let cpu = Cpu::current();
     cpu.proc = null_mut();
+    cpu.tick_start = arch::clock::time();
+    RUN_QUEUES[cpu_id].online = true;
 
     loop {
         // Avoid deadlock by ensuring that devices can interrupt.
         arch::interrupt::enable_interrupts();
 
-        for p in PROCESSES.iter_mut() {
-            let _guard = p.lock.lock();
-            if p.state == ProcessState::Runnable {
-                // Switch to the chosen process. It's the process's job
-                // to release its lock and then reacquire it before
-                // jumping back to us.
-                p.state = ProcessState::Running;
-                cpu.proc = addr_of!(*p).cast_mut();
-
-                // Run the process.
-                swtch(addr_of_mut!(cpu.context), addr_of_mut!(p.context));
-
-                // Process is done running for now.
-                // It should have changed its state before coming back.
-                cpu.proc = null_mut();
+        // Before deciding to wait, look again with interrupts off, so that
+        // one which makes a process runnable after the look stays pending
+        // and ends the wait, rather than being handled just before it.
+        let p = next_process(cpu_id).or_else(|| {
+            arch::interrupt::disable_interrupts();
+            next_process(cpu_id)
+        });
+        let Some(p) = p else {
+            // Nothing to run, so stop this hart until an interrupt,
+            // which may make a process runnable.
+            #[cfg(feature = "tickless")]
+            program_timer();
+            arch::interrupt::wait_for_interrupt();
+            continue;
+        };
+        let p = &mut *p;
+
+        let _guard = p.lock.lock();
+        if p.state == ProcessState::Runnable && !may_run_on(p, cpu_id) {
+            // Its affinity or reservation changed after it was picked.
+            make_runnable(addr_of!(*p).cast_mut());
+        } else if p.state == ProcessState::Runnable {
+            // Switch to the chosen process. It's the process's job
+            // to release its lock and then reacquire it before
+            // jumping back to us.
+            p.state = ProcessState::Running;
+            p.cpu = cpu_id;
+            p.time_slice = if p.reservation.is_realtime() {
+                p.reservation.budget
+            } else {
+                time_slice(p.priority)
+            };
+            #[cfg(feature = "tickless")]
+            charge_ticks_locked(false);
+            cpu.proc = addr_of!(*p).cast_mut();
+            #[cfg(feature = "tickless")]
+            program_timer();
+
+            // The process's kernel stack may have been mapped
+            // since this hart last flushed its TLB.
+            if p.index >= cpu.kernel_stacks_seen {
+                arch::mem::flush_cached_pages();
+                cpu.kernel_stacks_seen = p.index + 1;
             }
+
+            // Run the process.
+            swtch(addr_of_mut!(cpu.context), addr_of_mut!(p.context));
+
+            // Process is done running for now.
+            // It should have changed its state before coming back.
+            #[cfg(feature = "tickless")]
+            charge_ticks_locked(false);
+            cpu.proc = null_mut();
         }
     }
 }
@@ -121,7 +572,7 @@ pub unsafe extern "C" fn wakeup(chan: *mut c_void) {
         if !p.is_current() {
             let _guard = p.lock.lock();
             if p.state == ProcessState::Sleeping && p.chan == chan {
-                p.state = ProcessState::Runnable;
+                make_runnable(addr_of!(*p).cast_mut());
             }
         }
     }
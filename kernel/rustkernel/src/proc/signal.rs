@@ -0,0 +1,489 @@
+//! POSIX-style signals.
+//!
+//! A signal sent to a process is marked pending, and is delivered the next
+//! time the process returns to user space, unless it is blocked.
+//! Delivering a signal either takes its default action or runs the user's
+//! handler on the user stack, with the interrupted registers saved in a
+//! `SignalFrame` beneath it until the handler calls sigreturn().
+
+use super::{
+    process::{Process, ProcessError, ProcessState, INITPROC, WAIT_LOCK},
+    scheduler::{make_runnable, sched, wakeup},
+    table::PROCESSES,
+    trapframe::Trapframe,
+};
+use crate::hal::arch::virtual_memory::{copyin, copyout};
+use core::{
+    mem::size_of,
+    ptr::{addr_of, addr_of_mut},
+};
+
+/// Number of signals, including the unused signal 0.
+pub const NSIG: usize = 32;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGXCPU: usize = 24;
+
+/// Handler value that takes the signal's default action.
+pub const SIG_DFL: u64 = 0;
+/// Handler value that discards the signal.
+pub const SIG_IGN: u64 = 1;
+
+/// sigprocmask() operations.
+pub const SIG_BLOCK: i32 = 0;
+pub const SIG_UNBLOCK: i32 = 1;
+pub const SIG_SETMASK: i32 = 2;
+
+/// Signals that can't be caught, ignored or blocked.
+const UNBLOCKABLE: u32 = signal_bit(SIGKILL) | signal_bit(SIGSTOP);
+/// Signals that stop a process by default.
+const STOP_SIGNALS: u32 =
+    signal_bit(SIGSTOP) | signal_bit(SIGTSTP) | signal_bit(SIGTTIN) | signal_bit(SIGTTOU);
+
+pub const fn signal_bit(signal: usize) -> u32 {
+    1 << signal
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+impl DefaultAction {
+    pub fn of(signal: usize) -> DefaultAction {
+        match signal {
+            SIGCHLD => DefaultAction::Ignore,
+            SIGCONT => DefaultAction::Continue,
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+            _ => DefaultAction::Terminate,
+        }
+    }
+}
+
+/// What to do when a signal is delivered, as set by sigaction().
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SignalAction {
+    /// User address of the handler, or SIG_DFL or SIG_IGN.
+    pub handler: u64,
+    /// Signals to block while the handler runs.
+    pub mask: u32,
+}
+impl SignalAction {
+    pub const fn new() -> SignalAction {
+        SignalAction {
+            handler: SIG_DFL,
+            mask: 0,
+        }
+    }
+}
+
+/// Saved on the user stack while a signal handler runs.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SignalFrame {
+    /// The user registers to return to.
+    pub trapframe: Trapframe,
+    /// Blocked signals to restore.
+    pub blocked: u32,
+}
+
+impl Process {
+    /// Mark a signal as pending, and make sure the process
+    /// will notice it. p.lock must be held.
+    pub unsafe fn send_signal(&mut self, signal: usize) {
+        let bit = signal_bit(signal);
+
+        // It's too late for a process that has already exited.
+        if self.state == ProcessState::Zombie {
+            return;
+        }
+
+        if signal == SIGKILL {
+            self.killed = SIGKILL as i32;
+        } else if signal == SIGCONT {
+            self.pending_signals &= !STOP_SIGNALS;
+            self.stop_signal = 0;
+        } else if bit & STOP_SIGNALS != 0 {
+            self.pending_signals &= !signal_bit(SIGCONT);
+        }
+
+        let action = self.signal_actions[signal];
+        if action.handler == SIG_IGN
+            || (action.handler == SIG_DFL && DefaultAction::of(signal) == DefaultAction::Ignore)
+        {
+            // Discarded signals aren't left pending, except that
+            // SIGCONT still continues a stopped process.
+            if signal != SIGCONT {
+                return;
+            }
+        } else {
+            self.pending_signals |= bit;
+        }
+
+        // Default termination works like kill used to, so that
+        // sleeping system calls notice it and give up. A traced
+        // process only takes the signal if its tracer lets it.
+        if action.handler == SIG_DFL
+            && DefaultAction::of(signal) == DefaultAction::Terminate
+            && self.blocked_signals & bit == 0
+            && !self.traced
+        {
+            self.killed = signal as i32;
+        }
+
+        match self.state {
+            ProcessState::Stopped if signal == SIGCONT || signal == SIGKILL => {
+                make_runnable(addr_of_mut!(*self));
+            }
+            ProcessState::Sleeping if self.killed > 0 || self.has_caught_signal() => {
+                // Wake process from sleep(), so that a blocking
+                // system call can give up and let the handler run.
+                make_runnable(addr_of_mut!(*self));
+            }
+            ProcessState::Sleeping
+                if bit & STOP_SIGNALS != 0 && self.chan == addr_of!(*self).cast_mut().cast() =>
+            {
+                // Wake process from waiting for children, so that it stops.
+                make_runnable(addr_of_mut!(*self));
+            }
+            _ => {}
+        }
+    }
+
+    /// Take the lowest-numbered signal that is pending and not blocked.
+    /// A traced process can't block SIGTRAP, or it would keep
+    /// running into the same breakpoint.
+    fn take_signal(&mut self) -> Option<usize> {
+        let _guard = self.lock.lock();
+        let unblockable = if self.traced {
+            UNBLOCKABLE | signal_bit(SIGTRAP)
+        } else {
+            UNBLOCKABLE
+        };
+        let deliverable = self.pending_signals & !(self.blocked_signals & !unblockable);
+        if deliverable == 0 {
+            return None;
+        }
+        let signal = deliverable.trailing_zeros() as usize;
+        self.pending_signals &= !signal_bit(signal);
+        Some(signal)
+    }
+
+    /// Deliver pending signals before returning to user space.
+    ///
+    /// Returns once a handler has been set up to run, or there
+    /// are no more signals to deliver. Doesn't return if a
+    /// signal terminates the process.
+    pub unsafe fn deliver_signals(&mut self) {
+        while let Some(mut signal) = self.take_signal() {
+            if signal != SIGKILL && self.is_traced() {
+                // Stop for the tracer, which picks the signal to take, if any.
+                self.stop(signal);
+                match self.take_resume_signal() {
+                    Some(resume_signal) => signal = resume_signal,
+                    None => continue,
+                }
+            }
+
+            let action = self.signal_actions[signal];
+
+            match action.handler {
+                SIG_IGN => {}
+                SIG_DFL => match DefaultAction::of(signal) {
+                    DefaultAction::Terminate => {
+                        self.set_killed(signal);
+                        self.exit(-1);
+                    }
+                    DefaultAction::Stop => self.stop(signal),
+                    DefaultAction::Ignore | DefaultAction::Continue => {}
+                },
+                handler => {
+                    if self
+                        .setup_signal_frame(signal, handler, action.mask)
+                        .is_err()
+                    {
+                        // There's nowhere to run the handler.
+                        self.set_killed(SIGSEGV);
+                        self.exit(-1);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Stop until continued, letting a parent in waitpid() see the stop.
+    unsafe fn stop(&mut self, signal: usize) {
+        WAIT_LOCK.lock_unguarded();
+        if !self.parent.is_null() {
+            wakeup(self.parent.cast());
+        }
+
+        let _guard = self.lock.lock();
+        WAIT_LOCK.unlock();
+        // SIGCONT may have arrived since the signal was taken.
+        if self.pending_signals & signal_bit(SIGCONT) == 0 && self.killed == 0 {
+            self.state = ProcessState::Stopped;
+            self.stop_signal = signal as i32;
+            sched();
+        }
+    }
+
+    /// Stop now if a stop signal with its default action is pending,
+    /// for system calls that can sleep indefinitely, like waitpid().
+    /// Caller must not hold WAIT_LOCK.
+    pub unsafe fn stop_if_pending(&mut self) {
+        let signal = {
+            let _guard = self.lock.lock();
+            let stopping = self.pending_signals
+                & !self.blocked_signals
+                & STOP_SIGNALS
+                & !self.caught_signals();
+            if stopping == 0 {
+                return;
+            }
+            let signal = stopping.trailing_zeros() as usize;
+            self.pending_signals &= !signal_bit(signal);
+            signal
+        };
+        self.stop(signal);
+    }
+
+    /// Whether a blocking system call should give up, because the
+    /// process has been killed or has a signal for a handler to catch.
+    pub fn is_interrupted(&self) -> bool {
+        let _guard = self.lock.lock();
+        self.killed > 0 || self.has_caught_signal()
+    }
+    /// Whether a signal is ready to be delivered to a handler.
+    /// p.lock must be held.
+    fn has_caught_signal(&self) -> bool {
+        self.pending_signals & !self.blocked_signals & self.caught_signals() != 0
+    }
+
+    /// Signals with a handler installed.
+    fn caught_signals(&self) -> u32 {
+        (0..NSIG)
+            .filter(|signal| self.signal_actions[*signal].handler != SIG_DFL)
+            .fold(0, |caught, signal| caught | signal_bit(signal))
+    }
+
+    /// Save the user registers below the user stack, and set
+    /// up the trapframe to call handler(signal) on return to
+    /// user space, returning through sigreturn().
+    unsafe fn setup_signal_frame(
+        &mut self,
+        signal: usize,
+        handler: u64,
+        mask: u32,
+    ) -> Result<(), ProcessError> {
+        let mut frame = SignalFrame {
+            trapframe: *self.trapframe,
+            blocked: self.blocked_signals,
+        };
+        // riscv sp must be 16-byte aligned.
+        let sp = (*self.trapframe)
+            .sp
+            .wrapping_sub(size_of::<SignalFrame>() as u64)
+            & !0xf;
+
+        if copyout(
+            self.pagetable,
+            sp as usize,
+            addr_of_mut!(frame).cast(),
+            size_of::<SignalFrame>(),
+        ) < 0
+        {
+            return Err(ProcessError::PageError);
+        }
+
+        {
+            let _guard = self.lock.lock();
+            self.blocked_signals |= (mask | signal_bit(signal)) & !UNBLOCKABLE;
+        }
+
+        (*self.trapframe).epc = handler;
+        (*self.trapframe).a0 = signal as u64;
+        (*self.trapframe).sp = sp;
+        (*self.trapframe).ra = self.signal_restorer;
+        Ok(())
+    }
+
+    /// Restore the user registers and blocked signals saved
+    /// when a signal handler was called. The handler's stack
+    /// pointer is back where setup_signal_frame() left it.
+    ///
+    /// Returns the restored a0, which syscall() puts back in the trapframe.
+    pub unsafe fn sigreturn(&mut self) -> Result<u64, ProcessError> {
+        let mut frame = SignalFrame::default();
+
+        if copyin(
+            self.pagetable,
+            addr_of_mut!(frame).cast(),
+            (*self.trapframe).sp as usize,
+            size_of::<SignalFrame>(),
+        ) < 0
+        {
+            return Err(ProcessError::PageError);
+        }
+
+        (*self.trapframe).set_user_registers(&frame.trapframe);
+
+        let _guard = self.lock.lock();
+        self.blocked_signals = frame.blocked & !UNBLOCKABLE;
+        Ok((*self.trapframe).a0)
+    }
+
+    /// Examine and change the action taken on delivery of a signal.
+    /// `restorer` is where handlers return to, which should call sigreturn().
+    pub unsafe fn sigaction(
+        &mut self,
+        signal: usize,
+        action: Option<SignalAction>,
+        restorer: u64,
+    ) -> Result<SignalAction, ProcessError> {
+        if signal == 0 || signal >= NSIG {
+            return Err(ProcessError::InvalidArgument);
+        }
+
+        let old_action = self.signal_actions[signal];
+
+        if let Some(action) = action {
+            if signal_bit(signal) & UNBLOCKABLE != 0 {
+                return Err(ProcessError::InvalidArgument);
+            }
+
+            let _guard = self.lock.lock();
+            self.signal_actions[signal] = action;
+            self.signal_restorer = restorer;
+
+            // Setting a signal to be ignored discards it if pending.
+            if action.handler == SIG_IGN
+                || (action.handler == SIG_DFL && DefaultAction::of(signal) == DefaultAction::Ignore)
+            {
+                self.pending_signals &= !signal_bit(signal);
+            }
+        }
+
+        Ok(old_action)
+    }
+
+    /// Examine and change the set of blocked signals.
+    /// Returns the previously blocked signals.
+    pub fn sigprocmask(&mut self, how: i32, set: Option<u32>) -> Result<u32, ProcessError> {
+        let _guard = self.lock.lock();
+        let old_blocked = self.blocked_signals;
+
+        if let Some(set) = set {
+            self.blocked_signals = match how {
+                SIG_BLOCK => old_blocked | set,
+                SIG_UNBLOCK => old_blocked & !set,
+                SIG_SETMASK => set,
+                _ => return Err(ProcessError::InvalidArgument),
+            } & !UNBLOCKABLE;
+        }
+
+        Ok(old_blocked)
+    }
+
+    /// Send a signal to the process with the given pid, or for
+    /// pid 0 to the caller's process group, for pid -1 to every
+    /// process but init and the caller, and for other negative
+    /// pids to the process group -pid.
+    /// Signal 0 only checks that the target exists. Processes
+    /// of other users can only be signalled by root.
+    pub unsafe fn kill(pid: i32, signal: usize) -> Result<(), ProcessError> {
+        if signal >= NSIG {
+            return Err(ProcessError::InvalidArgument);
+        }
+        let current = Process::current().unwrap();
+        let uid = current.user_id();
+
+        match pid {
+            1.. => Process::with_pid(pid, |p| {
+                if !p.controllable_by(uid) {
+                    return Err(ProcessError::PermissionDenied);
+                }
+                if signal != 0 {
+                    p.send_signal(signal);
+                }
+                Ok(())
+            })
+            .ok_or(ProcessError::NoSuchProcess)?,
+            0 => {
+                let pgid = Process::get_pgid(0)?;
+                Process::kill_group(signal, |p| p.pgid == pgid && p.controllable_by(uid))
+            }
+            -1 => {
+                let current = current.pid;
+                Process::kill_group(signal, |p| {
+                    p.pid != current && addr_of!(*p) as usize != INITPROC && p.controllable_by(uid)
+                })
+            }
+            _ => Process::kill_group(signal, |p| p.pgid == -pid && p.controllable_by(uid)),
+        }
+    }
+
+    /// Send a signal to every process that matches `filter`.
+    /// Fails if none do.
+    pub unsafe fn kill_group(
+        signal: usize,
+        filter: impl Fn(&mut Process) -> bool,
+    ) -> Result<(), ProcessError> {
+        let mut found = false;
+
+        for p in PROCESSES.iter_mut() {
+            p.lock.lock_unguarded();
+
+            if p.state != ProcessState::Unused && filter(p) {
+                found = true;
+                if signal != 0 {
+                    p.send_signal(signal);
+                }
+            }
+
+            p.lock.unlock();
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(ProcessError::NoSuchProcess)
+        }
+    }
+
+    /// Reset caught signals to their default actions, since
+    /// exec() replaces the handlers' code.
+    pub fn reset_signal_actions(&mut self) {
+        let _guard = self.lock.lock();
+        for action in self.signal_actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::new();
+            }
+        }
+        self.signal_restorer = 0;
+    }
+}
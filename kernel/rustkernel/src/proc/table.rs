@@ -0,0 +1,196 @@
+//! The process table.
+//!
+//! Process structures are allocated from the kernel heap as they are
+//! needed, each with its own kernel stack, and linked into a list in the
+//! order they were created. They are never freed, only reused, so
+//! pointers to them stay valid and the list can be walked without a lock.
+//! Processes in use are also indexed by pid.
+
+use super::process::{Process, ProcessError, ProcessState};
+use crate::{
+    hal::arch::{mem::PAGE_SIZE, virtual_memory::kstackalloc},
+    mem::kalloc::{kalloc, kfree},
+    sync::spinlock::Spinlock,
+};
+use core::{
+    ptr::null_mut,
+    sync::atomic::{fence, Ordering},
+};
+
+/// Number of buckets in the pid index.
+const PID_BUCKETS: usize = 64;
+
+// Each process structure gets a page of its own.
+const _: () = assert!(core::mem::size_of::<Process>() <= PAGE_SIZE);
+
+pub static mut PROCESSES: ProcessTable = ProcessTable::new();
+
+pub struct ProcessTable {
+    /// Protects everything below, and the table_next
+    /// and pid_next links of each process.
+    lock: Spinlock,
+    /// First and last process structures created.
+    head: *mut Process,
+    tail: *mut Process,
+    /// Number of process structures created.
+    len: usize,
+    /// Number of processes that aren't ProcessState::Unused.
+    in_use: usize,
+    /// Maximum number of processes that can be in use at once.
+    max: usize,
+    /// Processes in use, by pid, chained through pid_next.
+    pids: [*mut Process; PID_BUCKETS],
+}
+impl ProcessTable {
+    pub const fn new() -> ProcessTable {
+        ProcessTable {
+            lock: Spinlock::new(),
+            head: null_mut(),
+            tail: null_mut(),
+            len: 0,
+            in_use: 0,
+            max: crate::NPROC,
+            pids: [null_mut(); PID_BUCKETS],
+        }
+    }
+
+    /// Iterate over every process structure, whatever its state.
+    pub fn iter(&self) -> impl Iterator<Item = &'static Process> {
+        self.iter_mut().map(|p| p as &Process)
+    }
+    pub fn iter_mut(&self) -> IterMut {
+        IterMut { next: self.head }
+    }
+
+    /// Number of processes that aren't ProcessState::Unused.
+    pub fn in_use(&self) -> usize {
+        let _guard = self.lock.lock();
+        self.in_use
+    }
+    pub fn max(&self) -> usize {
+        let _guard = self.lock.lock();
+        self.max
+    }
+    /// Change how many processes can be in use at once, up to NPROC,
+    /// the number of thread trapframe slots above the mmap() region.
+    /// Only a `privileged` caller may raise it. Processes already
+    /// in use beyond the new maximum are left alone.
+    pub fn set_max(&mut self, max: usize, privileged: bool) -> Result<(), ProcessError> {
+        if max == 0 || max > crate::NPROC {
+            return Err(ProcessError::InvalidArgument);
+        }
+        let _guard = self.lock.lock();
+        if max > self.max && !privileged {
+            return Err(ProcessError::PermissionDenied);
+        }
+        self.max = max;
+        Ok(())
+    }
+
+    /// Count a new process against the maximum,
+    /// before looking for a structure for it.
+    pub fn reserve(&mut self) -> Result<(), ProcessError> {
+        let _guard = self.lock.lock();
+        if self.in_use >= self.max {
+            return Err(ProcessError::MaxProcesses);
+        }
+        self.in_use += 1;
+        Ok(())
+    }
+    /// Give back a reservation that wasn't used.
+    pub fn unreserve(&mut self) {
+        let _guard = self.lock.lock();
+        self.in_use -= 1;
+    }
+
+    /// Allocate a new process structure and its kernel stack,
+    /// and add it to the table. Returns with p.lock held.
+    pub unsafe fn grow(&mut self) -> Result<&'static mut Process, ProcessError> {
+        let p = kalloc().cast::<Process>();
+        if p.is_null() {
+            return Err(ProcessError::Allocation);
+        }
+        p.write(Process::new());
+        let p = &mut *p;
+        p.state = ProcessState::Unused;
+        p.lock.lock_unguarded();
+
+        let _guard = self.lock.lock();
+        // The kernel page table is only extended with the table locked.
+        p.kernel_stack = kstackalloc(self.len) as u64;
+        if p.kernel_stack == 0 {
+            p.lock.unlock();
+            kfree((p as *mut Process).cast());
+            return Err(ProcessError::Allocation);
+        }
+        p.index = self.len;
+
+        // Make sure p is seen whole by anyone who finds it in the list.
+        fence(Ordering::Release);
+        if self.tail.is_null() {
+            self.head = p;
+        } else {
+            (*self.tail).table_next = p;
+        }
+        self.tail = p;
+        self.len += 1;
+
+        Ok(p)
+    }
+
+    /// Add a process that has just been given a pid to the pid index.
+    pub unsafe fn insert(&mut self, p: &mut Process) {
+        let _guard = self.lock.lock();
+        let bucket = &mut self.pids[p.pid as usize % PID_BUCKETS];
+        p.pid_next = *bucket;
+        *bucket = p;
+    }
+    /// Take a process that is being freed out of the pid
+    /// index, and stop counting it against the maximum.
+    pub unsafe fn remove(&mut self, p: &mut Process) {
+        let _guard = self.lock.lock();
+        let mut link = &mut self.pids[p.pid as usize % PID_BUCKETS];
+        while !link.is_null() {
+            if core::ptr::eq(*link, p) {
+                *link = p.pid_next;
+                break;
+            }
+            link = &mut (**link).pid_next;
+        }
+        p.pid_next = null_mut();
+        self.in_use -= 1;
+    }
+    /// Look up a process by pid. The process isn't locked, so the
+    /// caller should check its pid again once it has locked it.
+    pub unsafe fn find(&self, pid: i32) -> Option<&'static mut Process> {
+        if pid <= 0 {
+            return None;
+        }
+        let _guard = self.lock.lock();
+        let mut p = self.pids[pid as usize % PID_BUCKETS];
+        while !p.is_null() {
+            if (*p).pid == pid {
+                return Some(&mut *p);
+            }
+            p = (*p).pid_next;
+        }
+        None
+    }
+}
+
+pub struct IterMut {
+    next: *mut Process,
+}
+impl Iterator for IterMut {
+    type Item = &'static mut Process;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let p = unsafe { &mut *self.next };
+        self.next = p.table_next;
+        Some(p)
+    }
+}
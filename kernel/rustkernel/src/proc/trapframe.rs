@@ -1,3 +1,5 @@
+use core::ptr::addr_of;
+
 /// Per-process data for the trap handling code in trampoline.S.
 ///
 /// sits in a page by itself just under the trampoline page in the
@@ -97,4 +99,24 @@ impl Trapframe {
             t6: 0u64,
         }
     }
+    /// General-purpose register x`n`.
+    pub fn register(&self, n: usize) -> u64 {
+        assert!(n < 32, "register: no x{}", n);
+        if n == 0 {
+            0
+        } else {
+            // ra through t6 are x1 through x31, in order.
+            unsafe { *addr_of!(self.ra).add(n - 1) }
+        }
+    }
+    /// Replace the user registers with those in `registers`, keeping
+    /// the kernel's own fields, which user space could have overwritten.
+    pub fn set_user_registers(&mut self, registers: &Trapframe) {
+        let kernel = *self;
+        *self = *registers;
+        self.kernel_satp = kernel.kernel_satp;
+        self.kernel_sp = kernel.kernel_sp;
+        self.kernel_trap = kernel.kernel_trap;
+        self.kernel_hartid = kernel.kernel_hartid;
+    }
 }
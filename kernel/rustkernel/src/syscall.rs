@@ -1,20 +1,29 @@
 use crate::{
+    console,
     fs::{
-        file::{self, File},
-        inode::{ilock, iput, iunlock, namei},
+        file::{self, File, FileType, CONSOLE},
+        inode::{ilock, ipermission, iput, iunlock, namei, MAY_EXEC},
         log::LogOperation,
         stat::KIND_DIR,
     },
     hal::{
         arch::{
-            clock::CLOCK_TICKS,
-            virtual_memory::{copyin, copyinstr},
+            clock::{uptime, CLOCK_TICKS},
+            virtual_memory::{copyin, copyinstr, copyout},
         },
         platform::shutdown,
     },
     println,
-    proc::process::Process,
+    proc::{
+        alarm::{IntervalTimer, ITIMER_REAL},
+        limit::{proc_filelimit, ResourceLimit},
+        process::{Process, Times},
+        scheduler::{r#yield, SystemInfo},
+        signal::{SignalAction, SIGSEGV},
+        table::PROCESSES,
+    },
     string::strlen,
+    time::{clock_gettime, nanosleep, tick_time, wake_at, ClockError, Timespec},
     NOFILE,
 };
 use core::{
@@ -32,8 +41,24 @@ extern "C" {
     fn sys_unlink() -> u64;
     fn sys_link() -> u64;
     fn sys_mkdir() -> u64;
+    fn sys_chmod() -> u64;
+    fn sys_chown() -> u64;
 }
 
+/// Most arguments a syscall takes, in a0 through a5.
+const MAX_ARGUMENTS: usize = 6;
+
+// Every syscall's arguments fit in the registers that tracing prints.
+const _: () = {
+    let mut number = 0;
+    while number < 64 {
+        if let Some(syscall) = Syscall::from_number(number) {
+            assert!(syscall.signature().1 <= MAX_ARGUMENTS);
+        }
+        number += 1;
+    }
+};
+
 pub enum Syscall {
     Fork,
     Exit,
@@ -57,6 +82,43 @@ pub enum Syscall {
     Mkdir,
     Close,
     Shutdown,
+    Setpriority,
+    Getpriority,
+    SchedSetattr,
+    Times,
+    Clone,
+    Join,
+    Sigaction,
+    Sigprocmask,
+    Sigreturn,
+    Waitpid,
+    Setpgid,
+    Getpgid,
+    Setsid,
+    Tcsetpgrp,
+    Tcgetpgrp,
+    Maxproc,
+    SchedSetaffinity,
+    SchedGetaffinity,
+    Sysinfo,
+    Alarm,
+    Setitimer,
+    ClockGettime,
+    Nanosleep,
+    Getrlimit,
+    Setrlimit,
+    Setuid,
+    Getuid,
+    Setgid,
+    Getgid,
+    Chmod,
+    Chown,
+    Trace,
+    Ptrace,
+    Prctl,
+    Mmap,
+    Munmap,
+    Msync,
 }
 impl Syscall {
     pub unsafe fn call(&self) -> u64 {
@@ -73,6 +135,18 @@ impl Syscall {
                 Process::current().unwrap().wait_for_child(p).unwrap_or(-1) as i64 as u64
                 // process::wait(p) as u64
             }
+            Syscall::Waitpid => {
+                let mut pid = 0i32;
+                let mut p = 0u64;
+                let mut options = 0i32;
+                argint(0, addr_of_mut!(pid));
+                argaddr(1, addr_of_mut!(p));
+                argint(2, addr_of_mut!(options));
+                Process::current()
+                    .unwrap()
+                    .waitpid(pid, p, options)
+                    .unwrap_or(-1) as i64 as u64
+            }
             Syscall::Pipe => sys_pipe(),
             Syscall::Read => {
                 let mut file: *mut File = null_mut();
@@ -89,8 +163,18 @@ impl Syscall {
             }
             Syscall::Kill => {
                 let mut pid = 0i32;
+                let mut signal = 0i32;
                 argint(0, addr_of_mut!(pid));
-                Process::kill(pid) as u64
+                argint(1, addr_of_mut!(signal));
+
+                if signal < 0 {
+                    return -1i64 as u64;
+                }
+
+                match Process::kill(pid, signal as usize) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
             }
             Syscall::Exec => sys_exec(),
             Syscall::Fstat => {
@@ -107,7 +191,7 @@ impl Syscall {
             }
             Syscall::Chdir => {
                 let mut path = [0u8; crate::MAXPATH];
-                let proc = Process::current().unwrap();
+                let proc = Process::current().unwrap().leader();
 
                 let _operation = LogOperation::new();
 
@@ -119,7 +203,7 @@ impl Syscall {
                     return -1i64 as u64;
                 }
                 ilock(inode);
-                if (*inode).kind != KIND_DIR {
+                if (*inode).kind != KIND_DIR || ipermission(inode, MAY_EXEC) < 0 {
                     iunlock(inode);
                     iput(inode);
                     return -1i64 as u64;
@@ -160,19 +244,21 @@ impl Syscall {
                 let mut n = 0i32;
                 argint(0, addr_of_mut!(n));
 
+                let deadline = uptime() + n as usize;
                 let mut ticks = CLOCK_TICKS.lock_spinning();
 
-                while *ticks < *ticks + n as usize {
-                    if Process::current().unwrap().is_killed() {
+                while *ticks < deadline {
+                    if Process::current().unwrap().is_interrupted() {
                         return -1i64 as u64;
                     }
                     // Sleep until the value changes.
+                    wake_at(tick_time(deadline));
                     ticks.sleep(addr_of!(CLOCK_TICKS).cast_mut().cast());
                 }
                 0
             }
             // Returns how many clock tick interrupts have occured since start.
-            Syscall::Uptime => *CLOCK_TICKS.lock_spinning() as u64,
+            Syscall::Uptime => uptime() as u64,
             Syscall::Open => sys_open(),
             Syscall::Write => {
                 let mut file: *mut File = null_mut();
@@ -197,7 +283,8 @@ impl Syscall {
                 let mut file: *mut File = null_mut();
 
                 if argfd(0, addr_of_mut!(file_descriptor), addr_of_mut!(file)) >= 0 {
-                    Process::current().unwrap().open_files[file_descriptor as usize] = null_mut();
+                    Process::current().unwrap().leader().open_files[file_descriptor as usize] =
+                        null_mut();
                     file::fileclose(file);
                     0
                 } else {
@@ -205,6 +292,636 @@ impl Syscall {
                 }
             }
             Syscall::Shutdown => unsafe { shutdown() },
+            Syscall::Setpriority => {
+                let mut pid = 0i32;
+                let mut priority = 0i32;
+                argint(0, addr_of_mut!(pid));
+                argint(1, addr_of_mut!(priority));
+
+                match Process::set_priority(pid, priority) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Getpriority => {
+                let mut pid = 0i32;
+                argint(0, addr_of_mut!(pid));
+                Process::get_priority(pid).unwrap_or(-1) as i64 as u64
+            }
+            Syscall::SchedSetattr => {
+                let mut pid = 0i32;
+                let mut runtime = 0i32;
+                let mut deadline = 0i32;
+                let mut period = 0i32;
+                argint(0, addr_of_mut!(pid));
+                argint(1, addr_of_mut!(runtime));
+                argint(2, addr_of_mut!(deadline));
+                argint(3, addr_of_mut!(period));
+
+                if runtime < 0 || deadline < 0 || period < 0 {
+                    return -1i64 as u64;
+                }
+
+                match Process::set_reservation(
+                    pid,
+                    runtime as usize,
+                    deadline as usize,
+                    period as usize,
+                ) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            // Copies out the CPU time used, and returns the uptime like Syscall::Uptime.
+            Syscall::Times => {
+                let mut addr = 0u64;
+                argaddr(0, addr_of_mut!(addr));
+                let proc = Process::current().unwrap();
+                let mut times = proc.times();
+
+                if addr != 0
+                    && copyout(
+                        proc.pagetable,
+                        addr as usize,
+                        addr_of_mut!(times).cast(),
+                        size_of::<Times>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+                uptime() as u64
+            }
+            Syscall::Clone => {
+                let mut entry = 0u64;
+                let mut arg = 0u64;
+                let mut stack = 0u64;
+                argaddr(0, addr_of_mut!(entry));
+                argaddr(1, addr_of_mut!(arg));
+                argaddr(2, addr_of_mut!(stack));
+                Process::clone(entry, arg, stack).unwrap_or(-1) as i64 as u64
+            }
+            Syscall::Join => {
+                let mut stack = 0u64;
+                argaddr(0, addr_of_mut!(stack));
+                Process::current().unwrap().join(stack).unwrap_or(-1) as i64 as u64
+            }
+            Syscall::Sigaction => {
+                let mut signal = 0i32;
+                let mut action_addr = 0u64;
+                let mut old_action_addr = 0u64;
+                let mut restorer = 0u64;
+                argint(0, addr_of_mut!(signal));
+                argaddr(1, addr_of_mut!(action_addr));
+                argaddr(2, addr_of_mut!(old_action_addr));
+                // Passed along by the user library's sigaction().
+                argaddr(3, addr_of_mut!(restorer));
+                let proc = Process::current().unwrap();
+
+                let mut action = SignalAction::new();
+                if action_addr != 0
+                    && copyin(
+                        proc.pagetable,
+                        addr_of_mut!(action).cast(),
+                        action_addr as usize,
+                        size_of::<SignalAction>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+
+                let Ok(mut old_action) = proc.sigaction(
+                    signal as usize,
+                    (action_addr != 0).then_some(action),
+                    restorer,
+                ) else {
+                    return -1i64 as u64;
+                };
+
+                if old_action_addr != 0
+                    && copyout(
+                        proc.pagetable,
+                        old_action_addr as usize,
+                        addr_of_mut!(old_action).cast(),
+                        size_of::<SignalAction>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+                0
+            }
+            Syscall::Sigprocmask => {
+                let mut how = 0i32;
+                let mut set_addr = 0u64;
+                let mut old_set_addr = 0u64;
+                argint(0, addr_of_mut!(how));
+                argaddr(1, addr_of_mut!(set_addr));
+                argaddr(2, addr_of_mut!(old_set_addr));
+                let proc = Process::current().unwrap();
+
+                let mut set = 0u32;
+                if set_addr != 0
+                    && copyin(
+                        proc.pagetable,
+                        addr_of_mut!(set).cast(),
+                        set_addr as usize,
+                        size_of::<u32>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+
+                let Ok(mut old_set) = proc.sigprocmask(how, (set_addr != 0).then_some(set)) else {
+                    return -1i64 as u64;
+                };
+
+                if old_set_addr != 0
+                    && copyout(
+                        proc.pagetable,
+                        old_set_addr as usize,
+                        addr_of_mut!(old_set).cast(),
+                        size_of::<u32>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+                0
+            }
+            // Returns the interrupted a0, so that syscall() leaves it in place.
+            Syscall::Sigreturn => {
+                let proc = Process::current().unwrap();
+                match proc.sigreturn() {
+                    Ok(a0) => a0,
+                    // There's nothing sensible to return to.
+                    Err(_) => {
+                        proc.set_killed(SIGSEGV);
+                        proc.exit(-1)
+                    }
+                }
+            }
+            Syscall::Setpgid => {
+                let mut pid = 0i32;
+                let mut pgid = 0i32;
+                argint(0, addr_of_mut!(pid));
+                argint(1, addr_of_mut!(pgid));
+
+                match Process::set_pgid(pid, pgid) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Getpgid => {
+                let mut pid = 0i32;
+                argint(0, addr_of_mut!(pid));
+                Process::get_pgid(pid).unwrap_or(-1) as i64 as u64
+            }
+            Syscall::Setsid => Process::current().unwrap().set_sid().unwrap_or(-1) as i64 as u64,
+            // Sets the process group that the console sends ctrl-c and ctrl-z to.
+            Syscall::Tcsetpgrp => {
+                let mut file: *mut File = null_mut();
+                let mut pgid = 0i32;
+
+                if argfd(0, null_mut(), addr_of_mut!(file)) < 0 || !is_console(file) {
+                    return -1i64 as u64;
+                }
+                argint(1, addr_of_mut!(pgid));
+
+                let sid = Process::current().unwrap().sid;
+                if pgid <= 0 || !Process::group_exists(pgid, sid) {
+                    return -1i64 as u64;
+                }
+
+                console::set_foreground_group(pgid);
+                0
+            }
+            Syscall::Tcgetpgrp => {
+                let mut file: *mut File = null_mut();
+
+                if argfd(0, null_mut(), addr_of_mut!(file)) < 0 || !is_console(file) {
+                    return -1i64 as u64;
+                }
+                console::foreground_group() as i64 as u64
+            }
+            // Returns the maximum number of processes, after changing it if n > 0.
+            Syscall::Maxproc => {
+                let mut n = 0i32;
+                argint(0, addr_of_mut!(n));
+
+                let privileged = Process::current().unwrap().is_privileged();
+                if n < 0 || (n > 0 && PROCESSES.set_max(n as usize, privileged).is_err()) {
+                    return -1i64 as u64;
+                }
+                PROCESSES.max() as u64
+            }
+            Syscall::SchedSetaffinity => {
+                let mut pid = 0i32;
+                let mut affinity = 0u64;
+                argint(0, addr_of_mut!(pid));
+                argaddr(1, addr_of_mut!(affinity));
+
+                if Process::set_affinity(pid, affinity).is_err() {
+                    return -1i64 as u64;
+                }
+
+                // Leave a hart the caller may no longer run on.
+                let proc = Process::current().unwrap();
+                if proc.time_slice == 0 {
+                    r#yield();
+                }
+                0
+            }
+            // Returns the affinity mask.
+            Syscall::SchedGetaffinity => {
+                let mut pid = 0i32;
+                argint(0, addr_of_mut!(pid));
+                Process::get_affinity(pid).unwrap_or(-1i64 as u64)
+            }
+            // Returns the ticks that were left before the previous alarm.
+            Syscall::Alarm => {
+                let mut ticks = 0i32;
+                argint(0, addr_of_mut!(ticks));
+
+                if ticks < 0 {
+                    return -1i64 as u64;
+                }
+
+                let now = uptime();
+                let timer = IntervalTimer {
+                    interval: 0,
+                    value: ticks as u64,
+                };
+                Process::current()
+                    .unwrap()
+                    .set_interval_timer(timer, now)
+                    .map_or(-1i64 as u64, |old_timer| old_timer.value)
+            }
+            Syscall::Setitimer => {
+                let mut which = 0i32;
+                let mut timer_addr = 0u64;
+                let mut old_timer_addr = 0u64;
+                argint(0, addr_of_mut!(which));
+                argaddr(1, addr_of_mut!(timer_addr));
+                argaddr(2, addr_of_mut!(old_timer_addr));
+                let proc = Process::current().unwrap();
+
+                let mut timer = IntervalTimer::default();
+                if which != ITIMER_REAL
+                    || copyin(
+                        proc.pagetable,
+                        addr_of_mut!(timer).cast(),
+                        timer_addr as usize,
+                        size_of::<IntervalTimer>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+
+                let now = uptime();
+                let Ok(mut old_timer) = proc.set_interval_timer(timer, now) else {
+                    return -1i64 as u64;
+                };
+
+                if old_timer_addr != 0
+                    && copyout(
+                        proc.pagetable,
+                        old_timer_addr as usize,
+                        addr_of_mut!(old_timer).cast(),
+                        size_of::<IntervalTimer>(),
+                    ) < 0
+                {
+                    return -1i64 as u64;
+                }
+                0
+            }
+            Syscall::ClockGettime => {
+                let mut clock = 0i32;
+                let mut addr = 0u64;
+                argint(0, addr_of_mut!(clock));
+                argaddr(1, addr_of_mut!(addr));
+
+                let Ok(mut time) = clock_gettime(clock) else {
+                    return -1i64 as u64;
+                };
+                copyout(
+                    Process::current().unwrap().pagetable,
+                    addr as usize,
+                    addr_of_mut!(time).cast(),
+                    size_of::<Timespec>(),
+                ) as i64 as u64
+            }
+            // Copies out the time that was left if a signal interrupts the sleep.
+            Syscall::Nanosleep => {
+                let mut addr = 0u64;
+                let mut remaining_addr = 0u64;
+                argaddr(0, addr_of_mut!(addr));
+                argaddr(1, addr_of_mut!(remaining_addr));
+                let proc = Process::current().unwrap();
+
+                let mut duration = Timespec::default();
+                if copyin(
+                    proc.pagetable,
+                    addr_of_mut!(duration).cast(),
+                    addr as usize,
+                    size_of::<Timespec>(),
+                ) < 0
+                {
+                    return -1i64 as u64;
+                }
+
+                match nanosleep(duration) {
+                    Ok(_) => 0,
+                    Err(ClockError::Interrupted(mut remaining)) => {
+                        if remaining_addr != 0 {
+                            copyout(
+                                proc.pagetable,
+                                remaining_addr as usize,
+                                addr_of_mut!(remaining).cast(),
+                                size_of::<Timespec>(),
+                            );
+                        }
+                        -1i64 as u64
+                    }
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Getrlimit => {
+                let mut resource = 0i32;
+                let mut addr = 0u64;
+                argint(0, addr_of_mut!(resource));
+                argaddr(1, addr_of_mut!(addr));
+                let proc = Process::current().unwrap();
+
+                let Ok(mut limit) = proc.limit(resource as usize) else {
+                    return -1i64 as u64;
+                };
+                copyout(
+                    proc.pagetable,
+                    addr as usize,
+                    addr_of_mut!(limit).cast(),
+                    size_of::<ResourceLimit>(),
+                ) as i64 as u64
+            }
+            Syscall::Setrlimit => {
+                let mut resource = 0i32;
+                let mut addr = 0u64;
+                argint(0, addr_of_mut!(resource));
+                argaddr(1, addr_of_mut!(addr));
+                let proc = Process::current().unwrap();
+
+                let mut limit = ResourceLimit::unlimited();
+                if copyin(
+                    proc.pagetable,
+                    addr_of_mut!(limit).cast(),
+                    addr as usize,
+                    size_of::<ResourceLimit>(),
+                ) < 0
+                {
+                    return -1i64 as u64;
+                }
+
+                match proc.set_limit(resource as usize, limit) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Setuid => {
+                let mut uid = 0i32;
+                argint(0, addr_of_mut!(uid));
+
+                match Process::current().unwrap().set_user_id(uid as u32) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Getuid => Process::current().unwrap().user_id() as u64,
+            Syscall::Setgid => {
+                let mut gid = 0i32;
+                argint(0, addr_of_mut!(gid));
+
+                match Process::current().unwrap().set_group_id(gid as u32) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Getgid => Process::current().unwrap().group_id() as u64,
+            Syscall::Chmod => sys_chmod(),
+            Syscall::Chown => sys_chown(),
+            Syscall::Trace => {
+                let mut mask = 0u64;
+                argaddr(0, addr_of_mut!(mask));
+                Process::current().unwrap().trace_mask = mask;
+                0
+            }
+            Syscall::Prctl => {
+                let mut option = 0i32;
+                let mut value = 0u64;
+                argint(0, addr_of_mut!(option));
+                argaddr(1, addr_of_mut!(value));
+
+                Process::current()
+                    .unwrap()
+                    .prctl(option, value)
+                    .unwrap_or(-1i64 as u64)
+            }
+            Syscall::Ptrace => {
+                let mut request = 0i32;
+                let mut pid = 0i32;
+                let mut addr = 0u64;
+                let mut data = 0u64;
+                argint(0, addr_of_mut!(request));
+                argint(1, addr_of_mut!(pid));
+                argaddr(2, addr_of_mut!(addr));
+                argaddr(3, addr_of_mut!(data));
+
+                match Process::current().unwrap().ptrace(request, pid, addr, data) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Mmap => {
+                let mut length = 0u64;
+                let mut prot = 0i32;
+                let mut flags = 0i32;
+                let mut file_descriptor = 0i32;
+                let mut offset = 0u64;
+                // The address is only a hint, which is ignored.
+                argaddr(1, addr_of_mut!(length));
+                argint(2, addr_of_mut!(prot));
+                argint(3, addr_of_mut!(flags));
+                argint(4, addr_of_mut!(file_descriptor));
+                argaddr(5, addr_of_mut!(offset));
+
+                Process::current()
+                    .unwrap()
+                    .mmap(length, prot, flags, file_descriptor, offset)
+                    .unwrap_or(-1i64 as u64)
+            }
+            Syscall::Munmap => {
+                let mut addr = 0u64;
+                let mut length = 0u64;
+                argaddr(0, addr_of_mut!(addr));
+                argaddr(1, addr_of_mut!(length));
+
+                match Process::current().unwrap().munmap(addr, length) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Msync => {
+                let mut addr = 0u64;
+                let mut length = 0u64;
+                // Writes are always synchronous, so the flags are ignored.
+                argaddr(0, addr_of_mut!(addr));
+                argaddr(1, addr_of_mut!(length));
+
+                match Process::current().unwrap().msync(addr, length) {
+                    Ok(_) => 0,
+                    Err(_) => -1i64 as u64,
+                }
+            }
+            Syscall::Sysinfo => {
+                let mut addr = 0u64;
+                argaddr(0, addr_of_mut!(addr));
+                let mut info = SystemInfo::current();
+
+                if copyout(
+                    Process::current().unwrap().pagetable,
+                    addr as usize,
+                    addr_of_mut!(info).cast(),
+                    size_of::<SystemInfo>(),
+                ) < 0
+                {
+                    return -1i64 as u64;
+                }
+                0
+            }
+        }
+    }
+    const fn from_number(value: usize) -> Option<Syscall> {
+        match value {
+            1 => Some(Syscall::Fork),
+            2 => Some(Syscall::Exit),
+            3 => Some(Syscall::Wait),
+            4 => Some(Syscall::Pipe),
+            5 => Some(Syscall::Read),
+            6 => Some(Syscall::Kill),
+            7 => Some(Syscall::Exec),
+            8 => Some(Syscall::Fstat),
+            9 => Some(Syscall::Chdir),
+            10 => Some(Syscall::Dup),
+            11 => Some(Syscall::Getpid),
+            12 => Some(Syscall::Sbrk),
+            13 => Some(Syscall::Sleep),
+            14 => Some(Syscall::Uptime),
+            15 => Some(Syscall::Open),
+            16 => Some(Syscall::Write),
+            17 => Some(Syscall::Mknod),
+            18 => Some(Syscall::Unlink),
+            19 => Some(Syscall::Link),
+            20 => Some(Syscall::Mkdir),
+            21 => Some(Syscall::Close),
+            22 => Some(Syscall::Shutdown),
+            23 => Some(Syscall::Setpriority),
+            24 => Some(Syscall::Getpriority),
+            25 => Some(Syscall::SchedSetattr),
+            26 => Some(Syscall::Times),
+            27 => Some(Syscall::Clone),
+            28 => Some(Syscall::Join),
+            29 => Some(Syscall::Sigaction),
+            30 => Some(Syscall::Sigprocmask),
+            31 => Some(Syscall::Sigreturn),
+            32 => Some(Syscall::Waitpid),
+            33 => Some(Syscall::Setpgid),
+            34 => Some(Syscall::Getpgid),
+            35 => Some(Syscall::Setsid),
+            36 => Some(Syscall::Tcsetpgrp),
+            37 => Some(Syscall::Tcgetpgrp),
+            38 => Some(Syscall::Maxproc),
+            39 => Some(Syscall::SchedSetaffinity),
+            40 => Some(Syscall::SchedGetaffinity),
+            41 => Some(Syscall::Sysinfo),
+            42 => Some(Syscall::Alarm),
+            43 => Some(Syscall::Setitimer),
+            44 => Some(Syscall::ClockGettime),
+            45 => Some(Syscall::Nanosleep),
+            46 => Some(Syscall::Getrlimit),
+            47 => Some(Syscall::Setrlimit),
+            48 => Some(Syscall::Setuid),
+            49 => Some(Syscall::Getuid),
+            50 => Some(Syscall::Setgid),
+            51 => Some(Syscall::Getgid),
+            52 => Some(Syscall::Chmod),
+            53 => Some(Syscall::Chown),
+            54 => Some(Syscall::Trace),
+            55 => Some(Syscall::Ptrace),
+            56 => Some(Syscall::Prctl),
+            57 => Some(Syscall::Mmap),
+            58 => Some(Syscall::Munmap),
+            59 => Some(Syscall::Msync),
+            _ => None,
+        }
+    }
+    /// The syscall's name and how many arguments it takes, for tracing.
+    pub const fn signature(&self) -> (&'static str, usize) {
+        match self {
+            Syscall::Fork => ("fork", 0),
+            Syscall::Exit => ("exit", 1),
+            Syscall::Wait => ("wait", 1),
+            Syscall::Pipe => ("pipe", 1),
+            Syscall::Read => ("read", 3),
+            Syscall::Kill => ("kill", 2),
+            Syscall::Exec => ("exec", 2),
+            Syscall::Fstat => ("fstat", 2),
+            Syscall::Chdir => ("chdir", 1),
+            Syscall::Dup => ("dup", 1),
+            Syscall::Getpid => ("getpid", 0),
+            Syscall::Sbrk => ("sbrk", 1),
+            Syscall::Sleep => ("sleep", 1),
+            Syscall::Uptime => ("uptime", 0),
+            Syscall::Open => ("open", 2),
+            Syscall::Write => ("write", 3),
+            Syscall::Mknod => ("mknod", 3),
+            Syscall::Unlink => ("unlink", 1),
+            Syscall::Link => ("link", 2),
+            Syscall::Mkdir => ("mkdir", 1),
+            Syscall::Close => ("close", 1),
+            Syscall::Shutdown => ("shutdown", 0),
+            Syscall::Setpriority => ("setpriority", 2),
+            Syscall::Getpriority => ("getpriority", 1),
+            Syscall::SchedSetattr => ("sched_setattr", 4),
+            Syscall::Times => ("times", 1),
+            Syscall::Clone => ("clone", 3),
+            Syscall::Join => ("join", 1),
+            Syscall::Sigaction => ("sigaction", 3),
+            Syscall::Sigprocmask => ("sigprocmask", 3),
+            Syscall::Sigreturn => ("sigreturn", 0),
+            Syscall::Waitpid => ("waitpid", 3),
+            Syscall::Setpgid => ("setpgid", 2),
+            Syscall::Getpgid => ("getpgid", 1),
+            Syscall::Setsid => ("setsid", 0),
+            Syscall::Tcsetpgrp => ("tcsetpgrp", 2),
+            Syscall::Tcgetpgrp => ("tcgetpgrp", 1),
+            Syscall::Maxproc => ("maxproc", 1),
+            Syscall::SchedSetaffinity => ("sched_setaffinity", 2),
+            Syscall::SchedGetaffinity => ("sched_getaffinity", 1),
+            Syscall::Sysinfo => ("sysinfo", 1),
+            Syscall::Alarm => ("alarm", 1),
+            Syscall::Setitimer => ("setitimer", 3),
+            Syscall::ClockGettime => ("clock_gettime", 2),
+            Syscall::Nanosleep => ("nanosleep", 2),
+            Syscall::Getrlimit => ("getrlimit", 2),
+            Syscall::Setrlimit => ("setrlimit", 2),
+            Syscall::Setuid => ("setuid", 1),
+            Syscall::Getuid => ("getuid", 0),
+            Syscall::Setgid => ("setgid", 1),
+            Syscall::Getgid => ("getgid", 0),
+            Syscall::Chmod => ("chmod", 2),
+            Syscall::Chown => ("chown", 3),
+            Syscall::Trace => ("trace", 1),
+            Syscall::Ptrace => ("ptrace", 4),
+            Syscall::Prctl => ("prctl", 2),
+            Syscall::Mmap => ("mmap", 6),
+            Syscall::Munmap => ("munmap", 2),
+            Syscall::Msync => ("msync", 2),
         }
     }
 }
@@ -212,31 +929,7 @@ impl TryFrom<usize> for Syscall {
     type Error = ();
 
     fn try_from(value: usize) -> core::result::Result<Self, Self::Error> {
-        match value {
-            1 => Ok(Syscall::Fork),
-            2 => Ok(Syscall::Exit),
-            3 => Ok(Syscall::Wait),
-            4 => Ok(Syscall::Pipe),
-            5 => Ok(Syscall::Read),
-            6 => Ok(Syscall::Kill),
-            7 => Ok(Syscall::Exec),
-            8 => Ok(Syscall::Fstat),
-            9 => Ok(Syscall::Chdir),
-            10 => Ok(Syscall::Dup),
-            11 => Ok(Syscall::Getpid),
-            12 => Ok(Syscall::Sbrk),
-            13 => Ok(Syscall::Sleep),
-            14 => Ok(Syscall::Uptime),
-            15 => Ok(Syscall::Open),
-            16 => Ok(Syscall::Write),
-            17 => Ok(Syscall::Mknod),
-            18 => Ok(Syscall::Unlink),
-            19 => Ok(Syscall::Link),
-            20 => Ok(Syscall::Mkdir),
-            21 => Ok(Syscall::Close),
-            22 => Ok(Syscall::Shutdown),
-            _ => Err(()),
-        }
+        Syscall::from_number(value).ok_or(())
     }
 }
 impl From<Syscall> for usize {
@@ -264,10 +957,52 @@ impl From<Syscall> for usize {
             Syscall::Mkdir => 20,
             Syscall::Close => 21,
             Syscall::Shutdown => 22,
+            Syscall::Setpriority => 23,
+            Syscall::Getpriority => 24,
+            Syscall::SchedSetattr => 25,
+            Syscall::Times => 26,
+            Syscall::Clone => 27,
+            Syscall::Join => 28,
+            Syscall::Sigaction => 29,
+            Syscall::Sigprocmask => 30,
+            Syscall::Sigreturn => 31,
+            Syscall::Waitpid => 32,
+            Syscall::Setpgid => 33,
+            Syscall::Getpgid => 34,
+            Syscall::Setsid => 35,
+            Syscall::Tcsetpgrp => 36,
+            Syscall::Tcgetpgrp => 37,
+            Syscall::Maxproc => 38,
+            Syscall::SchedSetaffinity => 39,
+            Syscall::SchedGetaffinity => 40,
+            Syscall::Sysinfo => 41,
+            Syscall::Alarm => 42,
+            Syscall::Setitimer => 43,
+            Syscall::ClockGettime => 44,
+            Syscall::Nanosleep => 45,
+            Syscall::Getrlimit => 46,
+            Syscall::Setrlimit => 47,
+            Syscall::Setuid => 48,
+            Syscall::Getuid => 49,
+            Syscall::Setgid => 50,
+            Syscall::Getgid => 51,
+            Syscall::Chmod => 52,
+            Syscall::Chown => 53,
+            Syscall::Trace => 54,
+            Syscall::Ptrace => 55,
+            Syscall::Prctl => 56,
+            Syscall::Mmap => 57,
+            Syscall::Munmap => 58,
+            Syscall::Msync => 59,
         }
     }
 }
 
+/// Whether a file is the console device.
+unsafe fn is_console(file: *mut File) -> bool {
+    (*file).kind == FileType::Device && (*file).major as usize == CONSOLE
+}
+
 /// Fetch the u64 at addr from the current process.
 #[no_mangle]
 pub unsafe extern "C" fn fetchaddr(addr: u64, ip: *mut u64) -> i32 {
@@ -306,9 +1041,10 @@ pub unsafe extern "C" fn fetchstr(addr: u64, buf: *mut u8, max: i32) -> i32 {
 /// Allocate a file descriptor for the given file.
 /// Takes over file reference from caller on success.
 unsafe fn fdalloc(file: *mut File) -> Result<usize, ()> {
-    let proc = Process::current().unwrap();
+    let proc = Process::current().unwrap().leader();
+    let limit = proc_filelimit(proc) as usize;
 
-    for file_descriptor in 0..crate::NOFILE {
+    for file_descriptor in 0..limit {
         if proc.open_files[file_descriptor].is_null() {
             proc.open_files[file_descriptor] = file;
             return Ok(file_descriptor);
@@ -359,7 +1095,7 @@ pub unsafe extern "C" fn argfd(
         return -1;
     }
 
-    let file: *mut File = Process::current().unwrap().open_files[file_descriptor];
+    let file: *mut File = Process::current().unwrap().leader().open_files[file_descriptor];
     if file.is_null() {
         return -1;
     }
@@ -390,6 +1126,29 @@ pub unsafe fn syscall() {
     let num = (*proc.trapframe).a7;
 
     (*proc.trapframe).a0 = match TryInto::<Syscall>::try_into(num as usize) {
+        Ok(syscall) if num < 64 && proc.trace_mask & (1 << num) != 0 => {
+            let (name, arg_count) = syscall.signature();
+            let arguments: [u64; MAX_ARGUMENTS] = [
+                (*proc.trapframe).a0,
+                (*proc.trapframe).a1,
+                (*proc.trapframe).a2,
+                (*proc.trapframe).a3,
+                (*proc.trapframe).a4,
+                (*proc.trapframe).a5,
+            ];
+            let call = TracedCall {
+                name,
+                arguments: &arguments[..arg_count],
+            };
+
+            // exit() never returns to be printed afterwards.
+            if let Syscall::Exit = syscall {
+                println!("{}: {} = ?", proc.pid, call);
+            }
+            let result = syscall.call();
+            println!("{}: {} = {}", proc.pid, call, result as i64);
+            result
+        }
         Ok(syscall) => syscall.call(),
         Err(_) => {
             println!("{} unknown syscall {}", proc.pid, num);
@@ -397,3 +1156,21 @@ pub unsafe fn syscall() {
         }
     };
 }
+
+/// A syscall and its arguments, printed like a C call.
+struct TracedCall<'a> {
+    name: &'static str,
+    arguments: &'a [u64],
+}
+impl core::fmt::Display for TracedCall<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, argument) in self.arguments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", *argument as i64)?;
+        }
+        write!(f, ")")
+    }
+}
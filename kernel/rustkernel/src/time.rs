@@ -0,0 +1,131 @@
+//! Clocks with better resolution than the tick count.
+//!
+//! Both clocks read the CLINT's mtime counter directly, so they are as
+//! precise as the hardware timer, rather than the interval between ticks.
+
+use crate::{
+    hal::arch::clock::{
+        boot_time, interrupt_hart, time, time_since_boot, CLOCK_TICKS, TICK_INTERVAL,
+        TIMER_FREQUENCY,
+    },
+    proc::{process::Process, scheduler::r#yield},
+};
+use core::{
+    ptr::addr_of,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Time since some point in the past, which never goes backwards.
+pub const CLOCK_MONOTONIC: i32 = 1;
+/// Time since the kernel booted.
+pub const CLOCK_BOOTTIME: i32 = 7;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// mtime by which hart 0 should wake the processes sleeping on
+/// CLOCK_TICKS and check interval timers, for the tickless timer.
+static NEXT_WAKEUP: AtomicU64 = AtomicU64::new(u64::MAX);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClockError {
+    InvalidClock,
+    InvalidTime,
+    /// A signal cut a sleep short, with this much time left.
+    Interrupted(Timespec),
+}
+
+/// A time or duration, as used by clock_gettime() and nanosleep().
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Timespec {
+    pub seconds: u64,
+    pub nanoseconds: u64,
+}
+impl Timespec {
+    pub fn from_cycles(cycles: u64) -> Timespec {
+        Timespec {
+            seconds: cycles / TIMER_FREQUENCY,
+            nanoseconds: cycles % TIMER_FREQUENCY * NANOSECONDS_PER_SECOND / TIMER_FREQUENCY,
+        }
+    }
+    /// Convert to cycles of mtime, rounding up
+    /// so that sleeps are never cut short.
+    pub fn to_cycles(self) -> Result<u64, ClockError> {
+        if self.nanoseconds >= NANOSECONDS_PER_SECOND {
+            return Err(ClockError::InvalidTime);
+        }
+        let fraction = (self.nanoseconds * TIMER_FREQUENCY).div_ceil(NANOSECONDS_PER_SECOND);
+        self.seconds
+            .checked_mul(TIMER_FREQUENCY)
+            .and_then(|cycles| cycles.checked_add(fraction))
+            .ok_or(ClockError::InvalidTime)
+    }
+}
+
+/// mtime at which tick number `tick` starts.
+pub fn tick_time(tick: usize) -> u64 {
+    boot_time() + tick as u64 * TICK_INTERVAL
+}
+
+/// Make sure that hart 0 takes a timer interrupt by mtime `deadline`,
+/// waking everything sleeping on CLOCK_TICKS. Anything that sleeps
+/// until a time must call this first, though without the tickless
+/// timer there is already an interrupt every tick.
+pub fn wake_at(deadline: u64) {
+    if cfg!(feature = "tickless") && NEXT_WAKEUP.fetch_min(deadline, Ordering::AcqRel) > deadline {
+        unsafe { interrupt_hart(0) };
+    }
+}
+pub fn next_wakeup() -> u64 {
+    NEXT_WAKEUP.load(Ordering::Acquire)
+}
+/// Clear the wakeup deadline if it has passed by `now`,
+/// returning whether it had.
+pub fn take_wakeup(now: u64) -> bool {
+    NEXT_WAKEUP
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |deadline| {
+            (now >= deadline).then_some(u64::MAX)
+        })
+        .is_ok()
+}
+
+/// Read one of the clocks.
+pub fn clock_gettime(clock: i32) -> Result<Timespec, ClockError> {
+    match clock {
+        CLOCK_MONOTONIC => Ok(Timespec::from_cycles(time())),
+        CLOCK_BOOTTIME => Ok(Timespec::from_cycles(time_since_boot())),
+        _ => Err(ClockError::InvalidClock),
+    }
+}
+
+/// Sleep for `duration`.
+///
+/// The process sleeps through whole ticks, then yields until the
+/// deadline passes, since ticks are too coarse for the rest.
+/// The tickless timer can wake it right at the deadline instead.
+pub unsafe fn nanosleep(duration: Timespec) -> Result<(), ClockError> {
+    let deadline = time().saturating_add(duration.to_cycles()?);
+    let proc = Process::current().unwrap();
+
+    loop {
+        let now = time();
+        if now >= deadline {
+            return Ok(());
+        } else if proc.is_interrupted() {
+            return Err(ClockError::Interrupted(Timespec::from_cycles(
+                deadline - now,
+            )));
+        }
+
+        let mut ticks = CLOCK_TICKS.lock_spinning();
+        // With the tick count locked, the next tick can't be missed.
+        if cfg!(feature = "tickless") || deadline.saturating_sub(time()) > TICK_INTERVAL {
+            // The next tick comes before the deadline.
+            wake_at(deadline);
+            ticks.sleep(addr_of!(CLOCK_TICKS).cast_mut().cast());
+        } else {
+            drop(ticks);
+            r#yield();
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Signal numbers.
+#define SIGHUP   1
+#define SIGINT   2
+#define SIGQUIT  3
+#define SIGILL   4
+#define SIGTRAP  5
+#define SIGABRT  6
+#define SIGBUS   7
+#define SIGFPE   8
+#define SIGKILL  9
+#define SIGUSR1  10
+#define SIGSEGV  11
+#define SIGUSR2  12
+#define SIGPIPE  13
+#define SIGALRM  14
+#define SIGTERM  15
+#define SIGCHLD  17
+#define SIGCONT  18
+#define SIGSTOP  19
+#define SIGTSTP  20
+#define SIGTTIN  21
+#define SIGTTOU  22
+#define SIGXCPU  24
+#define NSIG     32
+
+#define SIG_DFL ((void (*)(int))0) // Take the default action
+#define SIG_IGN ((void (*)(int))1) // Discard the signal
+
+// sigprocmask() operations.
+#define SIG_BLOCK   0
+#define SIG_UNBLOCK 1
+#define SIG_SETMASK 2
+
+#define sigmask(sig) (1U << (sig))
+
+struct sigaction {
+  void (*sa_handler)(int); // Handler, SIG_DFL or SIG_IGN
+  uint sa_mask;            // Signals to block while the handler runs
+};
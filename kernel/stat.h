@@ -9,5 +9,8 @@ struct stat {
   uint ino;    // Inode number
   short type;  // Type of file
   short nlink; // Number of links to file
+  ushort mode; // Permission bits
   uint64 size; // Size of file in bytes
+  uint uid;    // Owner's user ID
+  uint gid;    // Owner's group ID
 };
@@ -21,3 +21,40 @@
 #define SYS_mkdir  20
 #define SYS_close  21
 #define SYS_shutdown  22
+#define SYS_setpriority 23
+#define SYS_getpriority 24
+#define SYS_sched_setattr 25
+#define SYS_times 26
+#define SYS_clone 27
+#define SYS_join 28
+#define SYS_sigaction 29
+#define SYS_sigprocmask 30
+#define SYS_sigreturn 31
+#define SYS_waitpid 32
+#define SYS_setpgid 33
+#define SYS_getpgid 34
+#define SYS_setsid 35
+#define SYS_tcsetpgrp 36
+#define SYS_tcgetpgrp 37
+#define SYS_maxproc 38
+#define SYS_sched_setaffinity 39
+#define SYS_sched_getaffinity 40
+#define SYS_sysinfo 41
+#define SYS_alarm 42
+#define SYS_setitimer 43
+#define SYS_clock_gettime 44
+#define SYS_nanosleep 45
+#define SYS_getrlimit 46
+#define SYS_setrlimit 47
+#define SYS_setuid 48
+#define SYS_getuid 49
+#define SYS_setgid 50
+#define SYS_getgid 51
+#define SYS_chmod 52
+#define SYS_chown 53
+#define SYS_trace 54
+#define SYS_ptrace 55
+#define SYS_prctl 56
+#define SYS_mmap 57
+#define SYS_munmap 58
+#define SYS_msync 59
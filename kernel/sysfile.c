@@ -26,9 +26,9 @@ static int
 fdalloc(struct file *f)
 {
   int fd;
-  struct proc *p = myproc();
+  struct proc *p = mygroupleader();
 
-  for (fd = 0; fd < NOFILE; fd++)
+  for (fd = 0; fd < proc_filelimit(p); fd++)
   {
     if (p->ofile[fd] == 0)
     {
@@ -71,7 +71,8 @@ sys_link(void)
   if ((dp = nameiparent(new, name)) == 0)
     goto bad;
   ilock(dp);
-  if (dp->dev != ip->dev || dirlink(dp, name, ip->inum) < 0)
+  if (dp->dev != ip->dev || ipermission(dp, MAY_WRITE | MAY_EXEC) < 0 ||
+      dirlink(dp, name, ip->inum) < 0)
   {
     iunlockput(dp);
     goto bad;
@@ -133,6 +134,9 @@ sys_unlink(void)
   if (namecmp(name, ".") == 0 || namecmp(name, "..") == 0)
     goto bad;
 
+  if (ipermission(dp, MAY_WRITE | MAY_EXEC) < 0)
+    goto bad;
+
   if ((ip = dirlookup(dp, name, &off)) == 0)
     goto bad;
   ilock(ip);
@@ -169,7 +173,7 @@ bad:
   return -1;
 }
 
-static struct inode *
+struct inode *
 create(char *path, short type, short major, short minor)
 {
   struct inode *ip, *dp;
@@ -190,7 +194,8 @@ create(char *path, short type, short major, short minor)
     return 0;
   }
 
-  if ((ip = ialloc(dp->dev, type)) == 0)
+  if (ipermission(dp, MAY_WRITE | MAY_EXEC) < 0 ||
+      (ip = ialloc(dp->dev, type)) == 0)
   {
     iunlockput(dp);
     return 0;
@@ -200,6 +205,14 @@ create(char *path, short type, short major, short minor)
   ip->major = major;
   ip->minor = minor;
   ip->nlink = 1;
+  ip->uid = proc_uid(myproc());
+  ip->gid = proc_gid(myproc());
+  if (type == T_DIR)
+    ip->mode = 0755;
+  else if (type == T_DEVICE)
+    ip->mode = 0666;
+  else
+    ip->mode = 0644;
   iupdate(ip);
 
   if (type == T_DIR)
@@ -236,7 +249,7 @@ uint64
 sys_open(void)
 {
   char path[MAXPATH];
-  int fd, omode;
+  int fd, omode, access;
   struct file *f;
   struct inode *ip;
   int n;
@@ -279,6 +292,19 @@ sys_open(void)
     return -1;
   }
 
+  access = 0;
+  if (!(omode & O_WRONLY))
+    access |= MAY_READ;
+  if ((omode & O_WRONLY) || (omode & O_RDWR) || (omode & O_TRUNC))
+    access |= MAY_WRITE;
+  // A running program can't be written.
+  if (ipermission(ip, access) < 0 || ((access & MAY_WRITE) && ip->textref > 0))
+  {
+    iunlockput(ip);
+    end_op();
+    return -1;
+  }
+
   if ((f = filealloc()) == 0 || (fd = fdalloc(f)) < 0)
   {
     if (f)
@@ -405,7 +431,7 @@ sys_pipe(void)
   uint64 fdarray; // user pointer to array of two integers
   struct file *rf, *wf;
   int fd0, fd1;
-  struct proc *p = myproc();
+  struct proc *p = mygroupleader();
 
   argaddr(0, &fdarray);
   if (pipealloc(&rf, &wf) < 0)
@@ -430,3 +456,68 @@ sys_pipe(void)
   }
   return 0;
 }
+
+// Change the permission bits of a file.
+// Only its owner or root may.
+uint64
+sys_chmod(void)
+{
+  char path[MAXPATH];
+  struct inode *ip;
+  int mode, uid;
+
+  argint(1, &mode);
+  if (argstr(0, path, MAXPATH) < 0)
+    return -1;
+
+  begin_op();
+  if ((ip = namei(path)) == 0)
+  {
+    end_op();
+    return -1;
+  }
+  ilock(ip);
+  uid = proc_uid(myproc());
+  if (uid != 0 && uid != ip->uid)
+  {
+    iunlockput(ip);
+    end_op();
+    return -1;
+  }
+  ip->mode = mode & 0777;
+  iupdate(ip);
+  iunlockput(ip);
+  end_op();
+  return 0;
+}
+
+// Change the owner and group of a file.
+// Only root may.
+uint64
+sys_chown(void)
+{
+  char path[MAXPATH];
+  struct inode *ip;
+  int uid, gid;
+
+  argint(1, &uid);
+  argint(2, &gid);
+  if (argstr(0, path, MAXPATH) < 0 || proc_uid(myproc()) != 0)
+    return -1;
+  if (uid < 0 || uid > 0xffff || gid < 0 || gid > 0xffff)
+    return -1;
+
+  begin_op();
+  if ((ip = namei(path)) == 0)
+  {
+    end_op();
+    return -1;
+  }
+  ilock(ip);
+  ip->uid = uid;
+  ip->gid = gid;
+  iupdate(ip);
+  iunlockput(ip);
+  end_op();
+  return 0;
+}
@@ -0,0 +1,11 @@
+// System-wide statistics, as reported by sysinfo().
+#define SI_LOAD_SHIFT 11 // Load averages are in fractions of 1 << SI_LOAD_SHIFT
+
+struct sysinfo {
+  uint64 uptime;      // Timer ticks since boot
+  uint64 loads[3];    // 1, 5 and 15 minute load averages
+  uint64 procs;       // Number of processes
+  uint64 ncpu;        // Number of harts running the scheduler
+  uint64 idle[NCPU];  // Timer ticks each hart has spent idle
+  uint64 intr[NCPU];  // Timer interrupts each hart has taken
+};
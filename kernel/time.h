@@ -0,0 +1,8 @@
+// Clocks for clock_gettime(), read from the CLINT's mtime counter.
+#define CLOCK_MONOTONIC 1 // Time since some point in the past, which never goes backwards
+#define CLOCK_BOOTTIME  7 // Time since the kernel booted
+
+struct timespec {
+  uint64 tv_sec;  // Seconds
+  uint64 tv_nsec; // Nanoseconds, less than 1000000000
+};
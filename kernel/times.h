@@ -0,0 +1,8 @@
+// CPU time used by a process and its waited-for children,
+// in timer ticks.
+struct tms {
+  uint64 utime;  // User ticks
+  uint64 stime;  // System ticks
+  uint64 cutime; // User ticks of waited-for children
+  uint64 cstime; // System ticks of waited-for children
+};
@@ -0,0 +1,11 @@
+// waitpid() options.
+#define WNOHANG   1 // Return 0 instead of waiting if no child has exited
+#define WUNTRACED 2 // Also return children that have stopped
+
+// Decoding the status reported by waitpid().
+#define WIFEXITED(status)   (((status) & 0x7f) == 0)  // Child called exit()
+#define WEXITSTATUS(status) (((status) >> 8) & 0xff)  // Its exit status
+#define WIFSIGNALED(status) (((status) & 0x7f) != 0 && ((status) & 0x7f) != 0x7f)  // Child was killed by a signal
+#define WTERMSIG(status)    ((status) & 0x7f)         // The signal that killed it
+#define WIFSTOPPED(status)  (((status) & 0xff) == 0x7f)  // Child was stopped by a signal
+#define WSTOPSIG(status)    (((status) >> 8) & 0xff)  // The signal that stopped it
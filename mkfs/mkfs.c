@@ -38,7 +38,7 @@ void wsect(uint, void*);
 void winode(uint, struct dinode*);
 void rinode(uint inum, struct dinode *ip);
 void rsect(uint sec, void *buf);
-uint ialloc(ushort type);
+uint ialloc(ushort type, ushort mode);
 void iappend(uint inum, void *p, int n);
 void die(const char *);
 
@@ -114,7 +114,7 @@ main(int argc, char *argv[])
   memmove(buf, &sb, sizeof(sb));
   wsect(1, buf);
 
-  rootino = ialloc(T_DIR);
+  rootino = ialloc(T_DIR, 0755);
   assert(rootino == ROOTINO);
 
   bzero(&de, sizeof(de));
@@ -146,10 +146,12 @@ main(int argc, char *argv[])
     // The binaries are named _rm, _cat, etc. to keep the
     // build operating system from trying to execute them
     // in place of system binaries like rm and cat.
-    if(shortname[0] == '_')
+    // Only they are executable; everything is owned by root.
+    if(shortname[0] == '_'){
       shortname += 1;
-
-    inum = ialloc(T_FILE);
+      inum = ialloc(T_FILE, 0755);
+    } else
+      inum = ialloc(T_FILE, 0644);
 
     bzero(&de, sizeof(de));
     de.inum = xshort(inum);
@@ -220,7 +222,7 @@ rsect(uint sec, void *buf)
 }
 
 uint
-ialloc(ushort type)
+ialloc(ushort type, ushort mode)
 {
   uint inum = freeinode++;
   struct dinode din;
@@ -228,6 +230,7 @@ ialloc(ushort type)
   bzero(&din, sizeof(din));
   din.type = xshort(type);
   din.nlink = xshort(1);
+  din.mode = xshort(mode);
   din.size = xint(0);
   winode(inum, &din);
   return inum;
@@ -0,0 +1,31 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int i, mode;
+  char *s;
+
+  if(argc < 3){
+    fprintf(2, "usage: chmod mode file...\n");
+    exit(1);
+  }
+
+  // The mode is in octal, as in 755.
+  mode = 0;
+  for(s = argv[1]; *s; s++){
+    if(*s < '0' || *s > '7'){
+      fprintf(2, "chmod: bad mode %s\n", argv[1]);
+      exit(1);
+    }
+    mode = mode * 8 + (*s - '0');
+  }
+
+  for(i = 2; i < argc; i++){
+    if(chmod(argv[i], mode) < 0)
+      fprintf(2, "chmod: %s failed\n", argv[i]);
+  }
+  exit(0);
+}
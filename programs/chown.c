@@ -0,0 +1,31 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int i, uid, gid;
+  char *s;
+
+  if(argc < 3){
+    fprintf(2, "usage: chown uid[:gid] file...\n");
+    exit(1);
+  }
+
+  // Without a group, the file keeps the user's ID as its group.
+  uid = atoi(argv[1]);
+  gid = uid;
+  for(s = argv[1]; *s; s++){
+    if(*s == ':'){
+      gid = atoi(s + 1);
+      break;
+    }
+  }
+
+  for(i = 2; i < argc; i++){
+    if(chown(argv[i], uid, gid) < 0)
+      fprintf(2, "chown: %s failed\n", argv[i]);
+  }
+  exit(0);
+}
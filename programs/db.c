@@ -0,0 +1,178 @@
+// A minimal debugger. Runs a program under ptrace(),
+// stopping before its first instruction.
+//
+// Commands:
+//   c         continue
+//   s         single-step one instruction
+//   b addr    set a breakpoint, which is removed when hit
+//   x addr    show the word at addr
+//   r         show the registers
+//   q         kill the program and quit
+
+#include "kernel/types.h"
+#include "kernel/signal.h"
+#include "kernel/wait.h"
+#include "kernel/ptrace.h"
+#include "user/user.h"
+
+#define EBREAK 0x9002 // c.ebreak
+
+char *regnames[31] = {
+  "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1",
+  "a2", "a3", "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6",
+  "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+};
+
+int pid;
+uint64 bpaddr;
+uint64 bpword; // The word the breakpoint was written into
+int bpset;
+
+uint64
+parsehex(char *s)
+{
+  uint64 n;
+
+  if(s[0] == '0' && s[1] == 'x')
+    s += 2;
+  n = 0;
+  for(;; s++){
+    if(*s >= '0' && *s <= '9')
+      n = n * 16 + *s - '0';
+    else if(*s >= 'a' && *s <= 'f')
+      n = n * 16 + *s - 'a' + 10;
+    else
+      return n;
+  }
+}
+
+// Wait for the program to stop, returning its pc,
+// or exit if it has finished.
+uint64
+waitstop(void)
+{
+  struct regs regs;
+  int status;
+
+  if(waitpid(pid, &status, 0) < 0){
+    fprintf(2, "db: waitpid failed\n");
+    exit(1);
+  }
+  if(WIFEXITED(status)){
+    printf("exited with status %d\n", WEXITSTATUS(status));
+    exit(0);
+  }
+  if(WIFSIGNALED(status)){
+    printf("killed by signal %d\n", WTERMSIG(status));
+    exit(0);
+  }
+
+  ptrace(PTRACE_GETREGS, pid, 0, &regs);
+  if(WSTOPSIG(status) == SIGTRAP && bpset && regs.epc == bpaddr){
+    // Put the instruction back, so the program can carry on from it.
+    ptrace(PTRACE_POKEDATA, pid, (void*)bpaddr, (void*)bpword);
+    bpset = 0;
+    printf("breakpoint at %p\n", regs.epc);
+  } else if(WSTOPSIG(status) != SIGTRAP){
+    printf("signal %d at %p\n", WSTOPSIG(status), regs.epc);
+  }
+  return regs.epc;
+}
+
+void
+breakpoint(uint64 addr)
+{
+  uint64 word;
+
+  if(bpset){
+    ptrace(PTRACE_POKEDATA, pid, (void*)bpaddr, (void*)bpword);
+    bpset = 0;
+  }
+  if(ptrace(PTRACE_PEEKDATA, pid, (void*)addr, &bpword) < 0){
+    printf("cannot read %p\n", addr);
+    return;
+  }
+  word = (bpword & ~0xffffULL) | EBREAK;
+  if(ptrace(PTRACE_POKEDATA, pid, (void*)addr, (void*)word) < 0){
+    printf("cannot write %p\n", addr);
+    return;
+  }
+  bpaddr = addr;
+  bpset = 1;
+}
+
+void
+showregs(void)
+{
+  struct regs regs;
+  int i;
+
+  ptrace(PTRACE_GETREGS, pid, 0, &regs);
+  printf("pc\t%p\n", regs.epc);
+  for(i = 0; i < 31; i++)
+    printf("%s\t%p\n", regnames[i], regs.x[i]);
+}
+
+int
+main(int argc, char *argv[])
+{
+  char buf[64];
+  uint64 word;
+
+  if(argc < 2){
+    fprintf(2, "usage: db program [arg...]\n");
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "db: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    // exec() stops a traced process with SIGTRAP.
+    ptrace(PTRACE_TRACEME, 0, 0, 0);
+    exec(argv[1], argv + 1);
+    fprintf(2, "db: exec %s failed\n", argv[1]);
+    exit(1);
+  }
+
+  printf("stopped at %p\n", waitstop());
+  for(;;){
+    printf("(db) ");
+    if(gets(buf, sizeof(buf))[0] == 0 || buf[0] == 'q')
+      break;
+
+    switch(buf[0]){
+    case 'c':
+      ptrace(PTRACE_CONT, pid, 0, 0);
+      waitstop();
+      break;
+    case 's':
+      ptrace(PTRACE_SINGLESTEP, pid, 0, 0);
+      printf("%p\n", waitstop());
+      break;
+    case 'b':
+      breakpoint(parsehex(buf + 2));
+      break;
+    case 'x':
+      if(ptrace(PTRACE_PEEKDATA, pid, (void*)parsehex(buf + 2), &word) < 0)
+        printf("cannot read\n");
+      else
+        printf("%p\n", word);
+      break;
+    case 'r':
+      showregs();
+      break;
+    case '\n':
+      break;
+    default:
+      printf("commands: c, s, b addr, x addr, r, q\n");
+      break;
+    }
+  }
+
+  ptrace(PTRACE_KILL, pid, 0, 0);
+  wait(0);
+  exit(0);
+}
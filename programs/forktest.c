@@ -5,8 +5,6 @@
 #include "kernel/stat.h"
 #include "user/user.h"
 
-#define N  1000
-
 void
 print(const char *s)
 {
@@ -16,11 +14,13 @@ print(const char *s)
 void
 forktest(void)
 {
-  int n, pid;
+  int n, pid, max;
 
   print("fork test\n");
 
-  for(n=0; n<N; n++){
+  // Forking as many times as there can be processes must fail.
+  max = maxproc(0);
+  for(n=0; n<max; n++){
     pid = fork();
     if(pid < 0)
       break;
@@ -28,8 +28,8 @@ forktest(void)
       exit(0);
   }
 
-  if(n == N){
-    print("fork claimed to work N times!\n");
+  if(n == max){
+    print("fork claimed to work maxproc times!\n");
     exit(1);
   }
 
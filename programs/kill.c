@@ -1,17 +1,26 @@
 #include "kernel/types.h"
 #include "kernel/stat.h"
+#include "kernel/signal.h"
 #include "user/user.h"
 
 int
 main(int argc, char **argv)
 {
-  int i;
+  int i, sig;
 
   if(argc < 2){
-    fprintf(2, "usage: kill pid...\n");
+    fprintf(2, "usage: kill [-signal] pid...\n");
     exit(1);
   }
-  for(i=1; i<argc; i++)
-    kill(atoi(argv[i]));
+  i = 1;
+  sig = SIGTERM;
+  if(argv[1][0] == '-'){
+    sig = atoi(argv[1] + 1);
+    i++;
+  }
+  for(; i<argc; i++){
+    if(kill(atoi(argv[i]), sig) < 0)
+      fprintf(2, "kill: %s failed\n", argv[i]);
+  }
   exit(0);
 }
@@ -22,6 +22,19 @@ fmtname(char *path)
   return buf;
 }
 
+// Permission bits as rwxrwxrwx.
+char*
+fmtmode(int mode)
+{
+  static char buf[10];
+  int i;
+
+  for(i = 0; i < 9; i++)
+    buf[i] = (mode & (0400 >> i)) ? "rwx"[i % 3] : '-';
+  buf[9] = 0;
+  return buf;
+}
+
 void
 ls(char *path)
 {
@@ -44,7 +57,8 @@ ls(char *path)
   switch(st.type){
   case T_DEVICE:
   case T_FILE:
-    printf("%s %d %d %l\n", fmtname(path), st.type, st.ino, st.size);
+    printf("%s %d %d %l %s %d %d\n", fmtname(path), st.type, st.ino, st.size,
+           fmtmode(st.mode), st.uid, st.gid);
     break;
 
   case T_DIR:
@@ -64,7 +78,8 @@ ls(char *path)
         printf("ls: cannot stat %s\n", buf);
         continue;
       }
-      printf("%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
+      printf("%s %d %d %d %s %d %d\n", fmtname(buf), st.type, st.ino, st.size,
+             fmtmode(st.mode), st.uid, st.gid);
     }
     break;
   }
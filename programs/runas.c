@@ -0,0 +1,22 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// Run a command as another user, which only root can do.
+int
+main(int argc, char *argv[])
+{
+  if(argc < 4){
+    fprintf(2, "usage: runas uid gid command [arg...]\n");
+    exit(1);
+  }
+
+  // Set the group first, while still root.
+  if(setgid(atoi(argv[2])) < 0 || setuid(atoi(argv[1])) < 0){
+    fprintf(2, "runas: cannot become %s:%s\n", argv[1], argv[2]);
+    exit(1);
+  }
+  exec(argv[3], argv + 3);
+  fprintf(2, "runas: exec %s failed\n", argv[3]);
+  exit(1);
+}
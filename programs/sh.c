@@ -3,6 +3,9 @@
 #include "kernel/types.h"
 #include "user/user.h"
 #include "kernel/fcntl.h"
+#include "kernel/resource.h"
+#include "kernel/signal.h"
+#include "kernel/wait.h"
 
 // Parsed command representation
 #define EXEC  1
@@ -12,6 +15,9 @@
 #define BACK  5
 
 #define MAXARGS 10
+#define MAXJOBS 8
+#define MAXLINE 100
+#define NLIMITS 5
 
 struct cmd {
   int type;
@@ -49,7 +55,29 @@ struct backcmd {
   struct cmd *cmd;
 };
 
+// Jobs that have been stopped or put in the background,
+// each in a process group named after its first process.
+struct job {
+  int pgid;     // 0 if the slot is free
+  int stopped;
+  char cmd[MAXLINE];
+} jobs[MAXJOBS];
+
+// Resource limits the ulimit builtin knows, by option letter.
+struct {
+  char opt;
+  int resource;
+  char *name;
+} limits[NLIMITS] = {
+  { 't', RLIMIT_CPU,    "cpu time (ticks)" },
+  { 'v', RLIMIT_AS,     "memory (bytes)" },
+  { 'n', RLIMIT_NOFILE, "open files" },
+  { 'u', RLIMIT_NPROC,  "child processes" },
+  { 'c', RLIMIT_CORE,   "core file size (bytes)" },
+};
+
 int fork1(void);  // Fork but panics on failure.
+void reapjobs(void);
 void panic(char*);
 struct cmd *parsecmd(char*);
 void runcmd(struct cmd*) __attribute__((noreturn));
@@ -134,6 +162,7 @@ runcmd(struct cmd *cmd)
 int
 getcmd(char *buf, int nbuf)
 {
+  reapjobs();
   write(2, "$ ", 2);
   memset(buf, 0, nbuf);
   gets(buf, nbuf);
@@ -142,11 +171,168 @@ getcmd(char *buf, int nbuf)
   return 0;
 }
 
+// Set what SIGINT and SIGTSTP do: the shell ignores them,
+// and the commands it runs take the default action.
+void
+setjobsignals(void (*handler)(int))
+{
+  struct sigaction sa;
+
+  sa.sa_handler = handler;
+  sa.sa_mask = 0;
+  sigaction(SIGINT, &sa, 0);
+  sigaction(SIGTSTP, &sa, 0);
+}
+
+void
+addjob(int pgid, char *cmd)
+{
+  int i;
+
+  for(i = 0; i < MAXJOBS; i++){
+    if(jobs[i].pgid == 0){
+      jobs[i].pgid = pgid;
+      jobs[i].stopped = 1;
+      strcpy(jobs[i].cmd, cmd);
+      return;
+    }
+  }
+  fprintf(2, "too many jobs\n");
+}
+
+// Find the job with the given pgid, or the latest job if pgid is 0.
+struct job*
+findjob(int pgid)
+{
+  int i;
+
+  for(i = MAXJOBS-1; i >= 0; i--)
+    if(jobs[i].pgid != 0 && (pgid == 0 || jobs[i].pgid == pgid))
+      return &jobs[i];
+  return 0;
+}
+
+// Give the console to a job and wait for it to finish or stop.
+void
+waitfg(int pgid, char *cmd)
+{
+  int status;
+
+  tcsetpgrp(0, pgid);
+  if(waitpid(pgid, &status, WUNTRACED) == pgid && WIFSTOPPED(status)){
+    addjob(pgid, cmd);
+    printf("[%d] stopped\n", pgid);
+  }
+  tcsetpgrp(0, getpgid(0));
+}
+
+// Collect background jobs that have finished or stopped.
+void
+reapjobs(void)
+{
+  int i, status, pid;
+
+  for(i = 0; i < MAXJOBS; i++){
+    if(jobs[i].pgid == 0)
+      continue;
+    pid = waitpid(jobs[i].pgid, &status, WNOHANG|WUNTRACED);
+    if(pid == jobs[i].pgid && WIFSTOPPED(status)){
+      jobs[i].stopped = 1;
+      printf("[%d] stopped\n", pid);
+    } else if(pid == jobs[i].pgid || pid < 0){
+      printf("[%d] done  %s", jobs[i].pgid, jobs[i].cmd);
+      jobs[i].pgid = 0;
+    }
+  }
+}
+
+// Run the jobs, fg and bg builtins, which must be run by the shell
+// itself. Returns 0 if buf isn't one of them.
+int
+jobcmd(char *buf)
+{
+  static char cmd[MAXLINE];
+  struct job *j;
+  int i, pgid;
+
+  if(strcmp(buf, "jobs\n") == 0){
+    for(i = 0; i < MAXJOBS; i++)
+      if(jobs[i].pgid != 0)
+        printf("[%d] %s  %s", jobs[i].pgid,
+               jobs[i].stopped ? "stopped" : "running", jobs[i].cmd);
+    return 1;
+  }
+
+  if((buf[0] != 'f' && buf[0] != 'b') || buf[1] != 'g' || (buf[2] != ' ' && buf[2] != '\n'))
+    return 0;
+
+  if((j = findjob(atoi(buf+2))) == 0){
+    fprintf(2, "%c%c: no such job\n", buf[0], buf[1]);
+    return 1;
+  }
+
+  if(buf[0] == 'b'){
+    j->stopped = 0;
+    kill(-j->pgid, SIGCONT);
+    printf("[%d] %s", j->pgid, j->cmd);
+    return 1;
+  }
+
+  pgid = j->pgid;
+  strcpy(cmd, j->cmd);
+  j->pgid = 0;
+  tcsetpgrp(0, pgid);
+  kill(-pgid, SIGCONT);
+  waitfg(pgid, cmd);
+  return 1;
+}
+
+// Run the ulimit builtin, which shows the soft limits, or with
+// "-opt value", sets both limits on one resource for the shell and
+// everything it runs after. Returns 0 if buf isn't ulimit.
+int
+limitcmd(char *buf)
+{
+  struct rlimit rl;
+  int i;
+
+  if(memcmp(buf, "ulimit", 6) != 0 || (buf[6] != ' ' && buf[6] != '\n'))
+    return 0;
+
+  if(buf[6] == '\n'){
+    for(i = 0; i < NLIMITS; i++){
+      getrlimit(limits[i].resource, &rl);
+      if(rl.rlim_cur == RLIM_INFINITY)
+        printf("-%c %s: unlimited\n", limits[i].opt, limits[i].name);
+      else
+        printf("-%c %s: %l\n", limits[i].opt, limits[i].name, rl.rlim_cur);
+    }
+    return 1;
+  }
+
+  for(i = 0; i < NLIMITS; i++)
+    if(buf[7] == '-' && buf[8] == limits[i].opt && buf[9] == ' ')
+      break;
+  if(i == NLIMITS){
+    fprintf(2, "usage: ulimit [-t|-v|-n|-u|-c ticks|bytes|files|children|bytes|unlimited]\n");
+    return 1;
+  }
+
+  if(memcmp(buf+10, "unlimited", 9) == 0)
+    rl.rlim_cur = RLIM_INFINITY;
+  else
+    rl.rlim_cur = atoi(buf+10);
+  rl.rlim_max = rl.rlim_cur;
+  if(setrlimit(limits[i].resource, &rl) < 0)
+    fprintf(2, "ulimit: cannot change %s limit\n", limits[i].name);
+  return 1;
+}
+
 int
 main(void)
 {
-  static char buf[100];
-  int fd;
+  static char buf[MAXLINE];
+  int fd, pid;
 
   // Ensure that three file descriptors are open.
   while((fd = open("console", O_RDWR)) >= 0){
@@ -156,6 +342,12 @@ main(void)
     }
   }
 
+  // Run each command in its own process group, and
+  // let only the foreground one be interrupted or stopped.
+  setsid();
+  setjobsignals(SIG_IGN);
+  tcsetpgrp(0, getpgid(0));
+
   // Read and run input commands.
   while(getcmd(buf, sizeof(buf)) >= 0){
     if(buf[0] == 'c' && buf[1] == 'd' && buf[2] == ' '){
@@ -165,9 +357,16 @@ main(void)
         fprintf(2, "cannot cd %s\n", buf+3);
       continue;
     }
-    if(fork1() == 0)
+    if(jobcmd(buf) || limitcmd(buf))
+      continue;
+    if((pid = fork1()) == 0){
+      setpgid(0, 0);
+      setjobsignals(SIG_DFL);
       runcmd(parsecmd(buf));
-    wait(0);
+    }
+    // Also set the group here, so it exists before waitfg() uses it.
+    setpgid(pid, pid);
+    waitfg(pid, buf);
   }
   exit(0);
 }
@@ -0,0 +1,43 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// Run a command, printing the syscalls it and its children make.
+// -e takes a comma-separated list of syscall numbers to trace
+// instead of all of them.
+int
+main(int argc, char *argv[])
+{
+  uint64 mask;
+  char *s;
+  int i, pid;
+
+  i = 1;
+  mask = ~0ULL;
+  if(argc > 2 && strcmp(argv[1], "-e") == 0){
+    mask = 0;
+    for(s = argv[2]; *s; s++){
+      if((s == argv[2] || s[-1] == ',') && atoi(s) < 64)
+        mask |= 1ULL << atoi(s);
+    }
+    i = 3;
+  }
+  if(i >= argc){
+    fprintf(2, "usage: strace [-e num,...] command [arg...]\n");
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "strace: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    trace(mask);
+    exec(argv[i], argv + i);
+    fprintf(2, "strace: exec %s failed\n", argv[i]);
+    exit(1);
+  }
+  wait(0);
+  exit(0);
+}
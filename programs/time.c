@@ -0,0 +1,42 @@
+// Run a command and report the CPU time it used.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/time.h"
+#include "kernel/times.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  struct tms t;
+  struct timespec start, end;
+  uint64 elapsed;
+  int pid;
+
+  if(argc < 2){
+    fprintf(2, "usage: time command [args...]\n");
+    exit(1);
+  }
+
+  clock_gettime(CLOCK_MONOTONIC, &start);
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "time: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    exec(argv[1], argv + 1);
+    fprintf(2, "time: exec %s failed\n", argv[1]);
+    exit(1);
+  }
+  wait(0);
+  clock_gettime(CLOCK_MONOTONIC, &end);
+  times(&t);
+
+  // Real time in microseconds.
+  elapsed = (end.tv_sec - start.tv_sec) * 1000000 + end.tv_nsec / 1000 - start.tv_nsec / 1000;
+  printf("%s: %l us real, %l user, %l system ticks\n",
+         argv[1], elapsed, t.cutime, t.cstime);
+  exit(0);
+}
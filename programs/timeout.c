@@ -0,0 +1,58 @@
+// Run a command, and kill it if it is still
+// running after the given number of ticks.
+
+#include "kernel/types.h"
+#include "kernel/signal.h"
+#include "kernel/wait.h"
+#include "user/user.h"
+
+int expired;
+
+void
+onalarm(int sig)
+{
+  expired = 1;
+}
+
+int
+main(int argc, char *argv[])
+{
+  struct sigaction sa;
+  int pid, status, ticks;
+
+  if(argc < 3){
+    fprintf(2, "usage: timeout ticks command [arg...]\n");
+    exit(1);
+  }
+  ticks = atoi(argv[1]);
+
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "timeout: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    exec(argv[2], argv + 2);
+    fprintf(2, "timeout: exec %s failed\n", argv[2]);
+    exit(1);
+  }
+
+  sa.sa_handler = onalarm;
+  sa.sa_mask = 0;
+  sigaction(SIGALRM, &sa, 0);
+  alarm(ticks);
+
+  // waitpid() fails when the alarm goes off.
+  while(waitpid(pid, &status, 0) < 0){
+    if(expired){
+      fprintf(2, "timeout: %s timed out\n", argv[2]);
+      kill(pid, SIGTERM);
+      expired = 0;
+    }
+  }
+  alarm(0);
+
+  if(WIFSIGNALED(status))
+    exit(124);
+  exit(WEXITSTATUS(status));
+}
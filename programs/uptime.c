@@ -0,0 +1,38 @@
+// Report how long the system has been up, the load averages,
+// and how long each hart has been idle and how many timer
+// interrupts it has taken.
+
+#include "kernel/types.h"
+#include "kernel/param.h"
+#include "kernel/sysinfo.h"
+#include "user/user.h"
+
+// Print a load average with two decimal places.
+void
+printload(uint64 load)
+{
+  uint64 hundredths = (load * 100 + (1 << (SI_LOAD_SHIFT - 1))) >> SI_LOAD_SHIFT;
+
+  printf("%l.%l%l", hundredths / 100, hundredths / 10 % 10, hundredths % 10);
+}
+
+int
+main(int argc, char *argv[])
+{
+  struct sysinfo info;
+  int i;
+
+  if(sysinfo(&info) < 0){
+    fprintf(2, "uptime: sysinfo failed\n");
+    exit(1);
+  }
+
+  printf("up %l ticks, %l processes, load average: ", info.uptime, info.procs);
+  for(i = 0; i < 3; i++){
+    printload(info.loads[i]);
+    printf(i < 2 ? ", " : "\n");
+  }
+  for(i = 0; i < info.ncpu; i++)
+    printf("hart %d: %l idle ticks, %l timer interrupts\n", i, info.idle[i], info.intr[i]);
+  exit(0);
+}
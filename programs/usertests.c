@@ -7,6 +7,10 @@
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/riscv.h"
+#include "kernel/signal.h"
+#include "kernel/mman.h"
+#include "kernel/itimer.h"
+#include "kernel/wait.h"
 
 //
 // Tests xv6 system calls.  usertests without arguments runs them all
@@ -805,7 +809,7 @@ killstatus(char *s)
       exit(0);
     }
     sleep(1);
-    kill(pid1);
+    kill(pid1, SIGKILL);
     wait(&xst);
     if(xst != -1) {
        printf("%s: status should be -1\n", s);
@@ -862,9 +866,9 @@ preempt(char *s)
   }
   close(pfds[0]);
   printf("kill... ");
-  kill(pid1);
-  kill(pid2);
-  kill(pid3);
+  kill(pid1, SIGKILL);
+  kill(pid2, SIGKILL);
+  kill(pid3, SIGKILL);
   printf("wait... ");
   wait(0);
   wait(0);
@@ -920,7 +924,7 @@ reparent(char *s)
     } else {
       int pid2 = fork();
       if(pid2 < 0){
-        kill(master_pid);
+        kill(master_pid, SIGKILL);
         exit(1);
       }
       exit(0);
@@ -1959,10 +1963,10 @@ iref(char *s)
 void
 forktest(char *s)
 {
-  enum{ N = 1000 };
-  int n, pid;
+  int n, pid, max;
 
-  for(n=0; n<N; n++){
+  max = maxproc(0);
+  for(n=0; n<max; n++){
     pid = fork();
     if(pid < 0)
       break;
@@ -1975,8 +1979,8 @@ forktest(char *s)
     exit(1);
   }
 
-  if(n == N){
-    printf("%s: fork claimed to work 1000 times!\n", s);
+  if(n == max){
+    printf("%s: fork claimed to work %d times!\n", s, max);
     exit(1);
   }
 
@@ -2200,7 +2204,7 @@ sbrkfail(char *s)
   for(i = 0; i < sizeof(pids)/sizeof(pids[0]); i++){
     if(pids[i] == -1)
       continue;
-    kill(pids[i]);
+    kill(pids[i], SIGKILL);
     wait(0);
   }
   if(c == (char*)0xffffffffffffffffL){
@@ -2571,6 +2575,610 @@ badarg(char *s)
   exit(0);
 }
 
+int countfree();
+
+// copy-on-write fork. the parent and child share their pages
+// after fork(), but each must see only its own writes.
+void
+cowfork(char *s)
+{
+  enum { N = 8 };
+  int pid, xstatus;
+  char *p = sbrk(N*PGSIZE);
+
+  if(p == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < N; i++)
+    p[i*PGSIZE] = 'a' + i;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    // the parent is writing the even pages.
+    for(int i = 1; i < N; i += 2){
+      if(p[i*PGSIZE] != 'a' + i)
+        exit(1);
+    }
+    for(int i = 0; i < N; i++)
+      p[i*PGSIZE] = 'c';
+    for(int i = 0; i < N; i++){
+      if(p[i*PGSIZE] != 'c')
+        exit(1);
+    }
+    exit(0);
+  }
+
+  for(int i = 0; i < N; i += 2)
+    p[i*PGSIZE] = 'p';
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child saw the wrong data\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < N; i++){
+    char want = (i % 2 == 0) ? 'p' : 'a' + i;
+    if(p[i*PGSIZE] != want){
+      printf("%s: parent saw the child's write\n", s);
+      exit(1);
+    }
+  }
+}
+
+// read() into a copy-on-write page has to copy it,
+// like a write from user space would.
+void
+cowcopyout(char *s)
+{
+  int a[2], b[2];
+  int pid, xstatus;
+  char *p = sbrk(PGSIZE);
+
+  if(p == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  p[0] = 'a';
+  if(pipe(a) < 0 || pipe(b) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    // waits for the parent to have read into its copy.
+    if(read(a[0], p + 1, 1) != 1 || p[0] != 'a')
+      exit(1);
+    if(read(a[0], p, 6) != 6 || strcmp(p, "child") != 0)
+      exit(1);
+    exit(0);
+  }
+
+  // the child still shares the page.
+  if(write(b[1], "parent", 7) != 7 || read(b[0], p, 7) != 7){
+    printf("%s: pipe i/o failed\n", s);
+    exit(1);
+  }
+  if(strcmp(p, "parent") != 0){
+    printf("%s: read() into a shared page failed\n", s);
+    exit(1);
+  }
+  if(write(a[1], "xchild", 7) != 7){
+    printf("%s: pipe write failed\n", s);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child saw the parent's read()\n", s);
+    exit(1);
+  }
+  if(strcmp(p, "parent") != 0){
+    printf("%s: parent saw the child's read()\n", s);
+    exit(1);
+  }
+  close(a[0]);
+  close(a[1]);
+  close(b[0]);
+  close(b[1]);
+}
+
+// pages shared by fork() must be freed once
+// neither the parent nor the child uses them.
+void
+cowfree(char *s)
+{
+  enum { N = 16 };
+  char *p = sbrk(N*PGSIZE);
+
+  if(p == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < N; i++)
+    p[i*PGSIZE] = i;
+
+  int free0 = countfree();
+  for(int n = 0; n < 10; n++){
+    int pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      for(int i = 0; i < N; i += 2)
+        p[i*PGSIZE] = 'c';
+      exit(0);
+    }
+    for(int i = 1; i < N; i += 2)
+      p[i*PGSIZE] = 'p';
+    wait(0);
+  }
+  int free1 = countfree();
+  if(free1 < free0){
+    printf("%s: lost some free pages %d (out of %d)\n", s, free1, free0);
+    exit(1);
+  }
+}
+
+// sbrk() only reserves memory, so an arena bigger than physical
+// memory is fine as long as only a few of its pages are used.
+void
+sbrklazy(char *s)
+{
+  uint64 size = 1024*1024*1024;
+  int fds[2];
+  char *a = sbrk(size);
+
+  if(a == (char*)-1){
+    printf("%s: sbrk of a sparse arena failed\n", s);
+    exit(1);
+  }
+  for(uint64 off = 0; off < size; off += size/8)
+    a[off] = 'a' + off/(size/8);
+  for(uint64 off = 0; off < size; off += size/8){
+    if(a[off] != 'a' + off/(size/8)){
+      printf("%s: wrong data in the arena\n", s);
+      exit(1);
+    }
+  }
+  if(a[PGSIZE] != 0){
+    printf("%s: unused heap page isn't zeroed\n", s);
+    exit(1);
+  }
+
+  // read() into a heap page that hasn't been used yet.
+  char *p = a + size - PGSIZE;
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(write(fds[1], "lazy", 5) != 5 || read(fds[0], p, 5) != 5){
+    printf("%s: read() into an unused heap page failed\n", s);
+    exit(1);
+  }
+  if(strcmp(p, "lazy") != 0){
+    printf("%s: read() into an unused heap page read the wrong data\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  if(sbrk(-size) == (char*)-1){
+    printf("%s: sbrk shrink failed\n", s);
+    exit(1);
+  }
+}
+
+// anonymous mmap() and munmap(), including unmapping the middle
+// of a mapping, fork() copying mappings, and running out of them.
+void
+mmapanon(char *s)
+{
+  int pid, xstatus;
+  char *p = mmap(0, 4*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+
+  if(p == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < 4*PGSIZE; i += PGSIZE){
+    if(p[i] != 0){
+      printf("%s: mapping isn't zeroed\n", s);
+      exit(1);
+    }
+    p[i] = 'a' + i/PGSIZE;
+  }
+
+  // unmapping the second page splits the mapping in two.
+  if(munmap(p + PGSIZE, PGSIZE) < 0){
+    printf("%s: munmap of the middle failed\n", s);
+    exit(1);
+  }
+  if(p[0] != 'a' || p[2*PGSIZE] != 'c' || p[3*PGSIZE] != 'd'){
+    printf("%s: munmap lost the rest of the mapping\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid == 0){
+    volatile char *hole = p + PGSIZE;
+    *hole = 1;
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: unmapped page is still there\n", s);
+    exit(1);
+  }
+
+  // fork() copies the mappings, which the child can then write.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(p[0] != 'a' || p[2*PGSIZE] != 'c' || p[3*PGSIZE] != 'd')
+      exit(1);
+    p[0] = 'x';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || p[0] != 'a'){
+    printf("%s: fork didn't copy the mapping\n", s);
+    exit(1);
+  }
+  if(munmap(p, 4*PGSIZE) < 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+
+  // each mapping takes one of a few slots, and splitting one takes another.
+  enum { MAX = 100 };
+  char *maps[MAX];
+  char *big = mmap(0, 3*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  int n;
+  if(big == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  for(n = 0; n < MAX; n++){
+    maps[n] = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+    if(maps[n] == MAP_FAILED)
+      break;
+  }
+  if(n == 0 || n == MAX){
+    printf("%s: could map %d pages\n", s, n);
+    exit(1);
+  }
+  if(munmap(big + PGSIZE, PGSIZE) == 0){
+    printf("%s: split a mapping with no slots left\n", s);
+    exit(1);
+  }
+  if(munmap(maps[n-1], PGSIZE) < 0 || munmap(big + PGSIZE, PGSIZE) < 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+  if(mmap(0, PGSIZE, PROT_READ, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0) != MAP_FAILED){
+    printf("%s: mapped past the limit\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < n-1; i++)
+    munmap(maps[i], PGSIZE);
+  munmap(big, 3*PGSIZE);
+}
+
+// the byte at offset off of the file name.
+char
+filebyte(char *s, char *name, int off)
+{
+  int fd = open(name, O_RDONLY);
+  if(fd < 0 || read(fd, buf, off + 1) != off + 1){
+    printf("%s: reading %s failed\n", s, name);
+    exit(1);
+  }
+  close(fd);
+  return buf[off];
+}
+
+// mmap() of a file. a MAP_SHARED mapping's writes reach the file on
+// munmap(), msync() and exit(), and a MAP_PRIVATE one's never do.
+void
+mmapfile(char *s)
+{
+  char *name = "mmapfile";
+  int fd, pid, xstatus;
+  char *p;
+
+  unlink(name);
+  fd = open(name, O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create %s failed\n", s, name);
+    exit(1);
+  }
+  for(int i = 0; i < 2; i++){
+    memset(buf, 'a' + i, PGSIZE);
+    if(write(fd, buf, PGSIZE) != PGSIZE){
+      printf("%s: write %s failed\n", s, name);
+      exit(1);
+    }
+  }
+
+  p = mmap(0, 2*PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(p == MAP_FAILED){
+    printf("%s: mmap of %s failed\n", s, name);
+    exit(1);
+  }
+  if(p[0] != 'a' || p[PGSIZE] != 'b'){
+    printf("%s: mapping doesn't hold the file\n", s);
+    exit(1);
+  }
+  p[0] = 'A';
+  if(munmap(p, 2*PGSIZE) < 0 || filebyte(s, name, 0) != 'A'){
+    printf("%s: munmap didn't write back\n", s);
+    exit(1);
+  }
+
+  p = mmap(0, 2*PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(p == MAP_FAILED){
+    printf("%s: mmap of %s failed\n", s, name);
+    exit(1);
+  }
+  p[1] = 'B';
+  if(msync(p, PGSIZE, MS_SYNC) < 0 || filebyte(s, name, 1) != 'B'){
+    printf("%s: msync didn't write back\n", s);
+    exit(1);
+  }
+  munmap(p, 2*PGSIZE);
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, PGSIZE);
+    if(p == MAP_FAILED)
+      exit(1);
+    p[2] = 'C';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || filebyte(s, name, PGSIZE + 2) != 'C'){
+    printf("%s: exit didn't write back\n", s);
+    exit(1);
+  }
+
+  // a private mapping's writes stay in the process that made them.
+  p = mmap(0, 2*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE, fd, 0);
+  if(p == MAP_FAILED){
+    printf("%s: mmap of %s failed\n", s, name);
+    exit(1);
+  }
+  p[0] = 'x';
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(p[0] != 'x')
+      exit(1);
+    p[0] = 'y';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || p[0] != 'x'){
+    printf("%s: private mapping is shared with the child\n", s);
+    exit(1);
+  }
+  if(munmap(p, 2*PGSIZE) < 0 || filebyte(s, name, 0) != 'A'){
+    printf("%s: private mapping was written back\n", s);
+    exit(1);
+  }
+
+  close(fd);
+  unlink(name);
+}
+
+#define NTHREAD 4
+
+int threadout[NTHREAD];
+
+void
+threadstore(void *arg)
+{
+  int i = (int)(uint64)arg;
+  threadout[i] = i + 1;
+  exit(0);
+}
+
+void
+threadspin(void *arg)
+{
+  for(;;)
+    ;
+}
+
+// clone() threads share memory with the process that made them,
+// join() waits for each in turn, and exit() of the process
+// takes its threads with it.
+void
+clonejoin(char *s)
+{
+  int tids[NTHREAD];
+  int pid, xstatus;
+
+  for(int i = 0; i < NTHREAD; i++){
+    tids[i] = thread_create(threadstore, (void*)(uint64)i);
+    if(tids[i] < 0){
+      printf("%s: thread_create failed\n", s);
+      exit(1);
+    }
+  }
+  for(int n = 0; n < NTHREAD; n++){
+    int tid = thread_join();
+    int found = 0;
+    for(int i = 0; i < NTHREAD; i++){
+      if(tids[i] == tid){
+        tids[i] = -1;
+        found = 1;
+      }
+    }
+    if(!found){
+      printf("%s: join returned %d\n", s, tid);
+      exit(1);
+    }
+  }
+  if(thread_join() >= 0){
+    printf("%s: join with no threads left succeeded\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < NTHREAD; i++){
+    if(threadout[i] != i + 1){
+      printf("%s: thread %d's write wasn't shared\n", s, i);
+      exit(1);
+    }
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(thread_create(threadspin, 0) < 0)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: thread_create in the child failed\n", s);
+    exit(1);
+  }
+}
+
+volatile int caught;
+volatile int heldback;
+
+void
+sigcatch(int sig)
+{
+  caught = sig;
+}
+
+// a handler for SIGUSR2 whose sa_mask blocks SIGUSR1.
+void
+sigmasked(int sig)
+{
+  caught = 0;
+  kill(getpid(), SIGUSR1);
+  heldback = (caught == 0);
+}
+
+// signal handlers, blocking signals with sigprocmask() and sa_mask,
+// and sigreturn() putting back the registers a signal interrupted.
+void
+signals(char *s)
+{
+  struct sigaction act;
+  uint mask = sigmask(SIGUSR1);
+  int pid, status;
+
+  act.sa_handler = sigcatch;
+  act.sa_mask = 0;
+  if(sigaction(SIGUSR1, &act, 0) < 0 || sigaction(SIGALRM, &act, 0) < 0){
+    printf("%s: sigaction failed\n", s);
+    exit(1);
+  }
+
+  // the handler runs before kill() returns, which still returns 0.
+  caught = 0;
+  if(kill(getpid(), SIGUSR1) != 0 || caught != SIGUSR1){
+    printf("%s: handler didn't run\n", s);
+    exit(1);
+  }
+
+  // a blocked signal waits until it is unblocked.
+  caught = 0;
+  if(sigprocmask(SIG_BLOCK, &mask, 0) < 0 || kill(getpid(), SIGUSR1) != 0){
+    printf("%s: sigprocmask failed\n", s);
+    exit(1);
+  }
+  if(caught != 0){
+    printf("%s: blocked signal was delivered\n", s);
+    exit(1);
+  }
+  if(sigprocmask(SIG_UNBLOCK, &mask, 0) < 0 || caught != SIGUSR1){
+    printf("%s: unblocked signal wasn't delivered\n", s);
+    exit(1);
+  }
+
+  // so does one blocked by the sa_mask of the handler running.
+  act.sa_handler = sigmasked;
+  act.sa_mask = mask;
+  if(sigaction(SIGUSR2, &act, 0) < 0 || kill(getpid(), SIGUSR2) != 0){
+    printf("%s: sigaction failed\n", s);
+    exit(1);
+  }
+  if(!heldback || caught != SIGUSR1){
+    printf("%s: sa_mask didn't hold back SIGUSR1\n", s);
+    exit(1);
+  }
+
+  // registers in use when a timer signal arrives must come back.
+  struct itimerval timer = { 1, 1 };
+  uint64 x = 1, y = 0, n = 0;
+  caught = 0;
+  setitimer(ITIMER_REAL, &timer, 0);
+  while(caught == 0 || n < 1000000){
+    x = x * 6364136223846793005ULL + 1442695040888963407ULL;
+    y ^= x >> 7;
+    n++;
+  }
+  timer.it_interval = timer.it_value = 0;
+  setitimer(ITIMER_REAL, &timer, 0);
+  uint64 x2 = 1, y2 = 0;
+  for(uint64 i = 0; i < n; i++){
+    x2 = x2 * 6364136223846793005ULL + 1442695040888963407ULL;
+    y2 ^= x2 >> 7;
+  }
+  if(x != x2 || y != y2){
+    printf("%s: registers changed across a signal\n", s);
+    exit(1);
+  }
+
+  // ignored signals do nothing, and default ones kill.
+  act.sa_handler = SIG_IGN;
+  act.sa_mask = 0;
+  if(sigaction(SIGUSR1, &act, 0) < 0 || kill(getpid(), SIGUSR1) != 0){
+    printf("%s: ignoring SIGUSR1 failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    act.sa_handler = SIG_DFL;
+    sigaction(SIGUSR1, &act, 0);
+    kill(getpid(), SIGUSR1);
+    exit(0);
+  }
+  if(waitpid(pid, &status, 0) != pid || !WIFSIGNALED(status) || WTERMSIG(status) != SIGUSR1){
+    printf("%s: SIGUSR1 didn't kill the child\n", s);
+    exit(1);
+  }
+}
+
 struct test {
   void (*f)(char *);
   char *s;
@@ -2635,6 +3243,14 @@ struct test {
   {sbrklast, "sbrklast"},
   {sbrk8000, "sbrk8000"},
   {badarg, "badarg" },
+  {cowfork, "cowfork"},
+  {cowcopyout, "cowcopyout"},
+  {cowfree, "cowfree"},
+  {sbrklazy, "sbrklazy"},
+  {mmapanon, "mmapanon"},
+  {mmapfile, "mmapfile"},
+  {clonejoin, "clonejoin"},
+  {signals, "signals"},
 
   { 0, 0},
 };
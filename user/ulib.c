@@ -145,3 +145,35 @@ memcpy(void *dst, const void *src, uint n)
 {
   return memmove(dst, src, n);
 }
+
+//
+// Start a thread running fn(arg) on a newly allocated
+// one-page stack. fn must call exit() when it's done.
+//
+int
+thread_create(void (*fn)(void*), void *arg)
+{
+  void *stack;
+  int tid;
+
+  if((stack = malloc(4096)) == 0)
+    return -1;
+  if((tid = clone(fn, arg, stack)) < 0)
+    free(stack);
+  return tid;
+}
+
+//
+// Wait for a thread started by thread_create() to exit,
+// and free its stack.
+//
+int
+thread_join(void)
+{
+  void *stack;
+  int tid;
+
+  if((tid = join(&stack)) >= 0)
+    free(stack);
+  return tid;
+}
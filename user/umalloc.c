@@ -1,10 +1,15 @@
 #include "../kernel/types.h"
 #include "../kernel/stat.h"
 #include "../kernel/param.h"
+#include "../kernel/mman.h"
 #include "user.h"
 
 // Memory allocator by Kernighan and Ritchie,
 // The C programming Language, 2nd ed.  Section 8.7.
+// Large blocks are mapped with mmap() instead, so that
+// free() can give them back to the kernel.
+
+#define MMAP_THRESHOLD (64*1024) // Bytes from which a block is mapped
 
 typedef long Align;
 
@@ -20,6 +25,7 @@ typedef union header Header;
 
 static Header base;
 static Header *freep;
+static Header mapped; // What a mapped block's ptr points at
 
 void
 free(void *ap)
@@ -27,6 +33,10 @@ free(void *ap)
   Header *bp, *p;
 
   bp = (Header*)ap - 1;
+  if(bp->s.ptr == &mapped){
+    munmap(bp, (uint64)bp->s.size * sizeof(Header));
+    return;
+  }
   for(p = freep; !(bp > p && bp < p->s.ptr); p = p->s.ptr)
     if(p >= p->s.ptr && (bp > p || bp < p->s.ptr))
       break;
@@ -60,6 +70,20 @@ morecore(uint nu)
   return freep;
 }
 
+static void*
+mapblock(uint nunits)
+{
+  Header *hp;
+
+  hp = mmap(0, (uint64)nunits * sizeof(Header), PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+  if(hp == MAP_FAILED)
+    return 0;
+  hp->s.ptr = &mapped;
+  hp->s.size = nunits;
+  return (void*)(hp + 1);
+}
+
 void*
 malloc(uint nbytes)
 {
@@ -67,6 +91,8 @@ malloc(uint nbytes)
   uint nunits;
 
   nunits = (nbytes + sizeof(Header) - 1)/sizeof(Header) + 1;
+  if(nbytes >= MMAP_THRESHOLD)
+    return mapblock(nunits);
   if((prevp = freep) == 0){
     base.s.ptr = freep = prevp = &base;
     base.s.size = 0;
@@ -1,4 +1,10 @@
 struct stat;
+struct tms;
+struct sigaction;
+struct sysinfo;
+struct itimerval;
+struct timespec;
+struct rlimit;
 
 // system calls
 int fork(void);
@@ -8,7 +14,7 @@ int pipe(int*);
 int write(int, const void*, int);
 int read(int, void*, int);
 int close(int);
-int kill(int);
+int kill(int, int);
 int exec(const char*, char**);
 int open(const char*, int);
 int mknod(const char*, short, short);
@@ -23,6 +29,43 @@ char* sbrk(int);
 int sleep(int);
 int uptime(void);
 void shutdown(void);
+int setpriority(int, int);
+int getpriority(int);
+int sched_setattr(int, int, int, int);
+int times(struct tms*);
+int clone(void (*)(void*), void*, void*);
+int join(void**);
+int sigaction(int, const struct sigaction*, struct sigaction*);
+int sigprocmask(int, uint*, uint*);
+int sigreturn(void);
+int waitpid(int, int*, int);
+int setpgid(int, int);
+int getpgid(int);
+int setsid(void);
+int tcsetpgrp(int, int);
+int tcgetpgrp(int);
+int maxproc(int);
+int sched_setaffinity(int, uint);
+int sched_getaffinity(int);
+int sysinfo(struct sysinfo*);
+int alarm(int);
+int setitimer(int, const struct itimerval*, struct itimerval*);
+int clock_gettime(int, struct timespec*);
+int nanosleep(const struct timespec*, struct timespec*);
+int getrlimit(int, struct rlimit*);
+int setrlimit(int, const struct rlimit*);
+int setuid(int);
+int getuid(void);
+int setgid(int);
+int getgid(void);
+int chmod(const char*, int);
+int chown(const char*, int, int);
+int trace(uint64);
+int ptrace(int, int, void*, void*);
+int prctl(int, uint64);
+void* mmap(void*, uint64, int, int, int, uint64);
+int munmap(void*, uint64);
+int msync(void*, uint64, int);
 
 // ulib.c
 int stat(const char*, struct stat*);
@@ -40,3 +83,5 @@ void free(void*);
 int atoi(const char*);
 int memcmp(const void *, const void *, uint);
 void *memcpy(void *, const void *, uint);
+int thread_create(void (*)(void*), void*);
+int thread_join(void);
@@ -37,3 +37,48 @@ entry("sbrk");
 entry("sleep");
 entry("uptime");
 entry("shutdown");
+entry("setpriority");
+entry("getpriority");
+entry("sched_setattr");
+entry("times");
+entry("clone");
+entry("join");
+entry("sigprocmask");
+entry("sigreturn");
+entry("waitpid");
+entry("setpgid");
+entry("getpgid");
+entry("setsid");
+entry("tcsetpgrp");
+entry("tcgetpgrp");
+entry("maxproc");
+entry("sched_setaffinity");
+entry("sched_getaffinity");
+entry("sysinfo");
+entry("alarm");
+entry("setitimer");
+entry("clock_gettime");
+entry("nanosleep");
+entry("getrlimit");
+entry("setrlimit");
+entry("setuid");
+entry("getuid");
+entry("setgid");
+entry("getgid");
+entry("chmod");
+entry("chown");
+entry("trace");
+entry("ptrace");
+entry("prctl");
+entry("mmap");
+entry("munmap");
+entry("msync");
+
+# sigaction also passes the address of sigreturn,
+# which signal handlers return to.
+print ".global sigaction\n";
+print "sigaction:\n";
+print " la a3, sigreturn\n";
+print " li a7, SYS_sigaction\n";
+print " ecall\n";
+print " ret\n";